@@ -0,0 +1,8 @@
+-- Optional limits checked on every submission of a field.
+
+ALTER TABLE fields ADD COLUMN min_value TEXT;
+ALTER TABLE fields ADD COLUMN max_value TEXT;
+ALTER TABLE fields ADD COLUMN min_length INTEGER;
+ALTER TABLE fields ADD COLUMN max_length INTEGER;
+ALTER TABLE fields ADD COLUMN pattern TEXT;
+ALTER TABLE fields ADD COLUMN allowed TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,4 @@
+-- Key used to sign the session cookies, 64 random bytes in hex.
+
+INSERT OR IGNORE INTO config (key, value) VALUES
+    ('session_key', lower(hex(randomblob(64))));
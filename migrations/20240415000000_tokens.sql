@@ -0,0 +1,11 @@
+-- Bearer tokens issued to API clients are revoked by their id until they
+-- expire.
+
+CREATE TABLE IF NOT EXISTS revoked_tokens(
+    jti TEXT PRIMARY KEY,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO config (key, value) VALUES
+    ('jwt_expires_in', '3600');
@@ -0,0 +1,4 @@
+-- Order of the fields inside their form.
+
+ALTER TABLE fields ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+UPDATE fields SET position = id;
@@ -0,0 +1,10 @@
+-- Deleted forms, fields and users are kept with the time they were deleted
+-- until they are restored or purged from the trash. Submissions get the same
+-- column when the table of their form is synced.
+
+ALTER TABLE forms ADD COLUMN deleted_at DATETIME;
+ALTER TABLE fields ADD COLUMN deleted_at DATETIME;
+ALTER TABLE users ADD COLUMN deleted_at DATETIME;
+
+INSERT OR IGNORE INTO config (key, value) VALUES
+    ('trash_retention_days', '30');
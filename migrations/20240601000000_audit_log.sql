@@ -0,0 +1,16 @@
+-- Who changed what: every change to forms, fields, users, parameters and
+-- submissions made through the application, with the entity before and
+-- after it as JSON.
+
+CREATE TABLE IF NOT EXISTS audit_log(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    before TEXT,
+    after TEXT,
+    created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_created_at ON audit_log(created_at);
@@ -0,0 +1,20 @@
+-- Who is told by email of the new submissions of every form, and the SMTP
+-- server that sends those emails. Without `smtp_host` nothing is sent.
+
+CREATE TABLE IF NOT EXISTS notifications(
+    form_id INTEGER PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    recipients TEXT NOT NULL DEFAULT '',
+    subject TEXT NOT NULL DEFAULT '',
+    include_answers BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO config (key, value) VALUES
+    ('smtp_host', ''),
+    ('smtp_port', '587'),
+    ('smtp_tls', 'starttls'),
+    ('smtp_username', ''),
+    ('smtp_password', ''),
+    ('smtp_from', ''),
+    ('smtp_retries', '3');
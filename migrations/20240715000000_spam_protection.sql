@@ -0,0 +1,18 @@
+-- Protection of the public forms against bots: a hidden field that people
+-- leave empty, a minimum time to fill the form, checked with a signed token,
+-- and a limit of submissions from the same address. Forms without a row get
+-- the honeypot and the limit, not the time trap, so custom templates that do
+-- not render its token keep working.
+
+CREATE TABLE IF NOT EXISTS protections(
+    form_id INTEGER PRIMARY KEY,
+    honeypot BOOLEAN NOT NULL DEFAULT TRUE,
+    honeypot_name TEXT NOT NULL DEFAULT 'website',
+    min_fill_seconds INTEGER NOT NULL DEFAULT 0,
+    rate_limit INTEGER NOT NULL DEFAULT 10,
+    rate_window INTEGER NOT NULL DEFAULT 60,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO config (key, value) VALUES
+    ('form_secret', lower(hex(randomblob(32))));
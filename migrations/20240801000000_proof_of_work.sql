@@ -0,0 +1,10 @@
+-- Optional proof-of-work challenge of the public forms: the leading zero
+-- bits the browser must find in a hash before sending the form, 0 to
+-- disable it, and the challenges already solved, so each one is used once.
+
+ALTER TABLE protections ADD COLUMN pow_difficulty INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS spent_challenges(
+    nonce TEXT PRIMARY KEY,
+    expires_at DATETIME NOT NULL
+);
@@ -0,0 +1,20 @@
+-- Files sent with the `file` fields. The column of the submission keeps the
+-- id of the upload. The content is kept here as a blob, or in `upload_dir`
+-- named after its SHA-256 when the configuration sets it, and then `data` is
+-- NULL. Every field may limit the size and the MIME types of its files.
+
+ALTER TABLE fields ADD COLUMN max_size INTEGER;
+ALTER TABLE fields ADD COLUMN mime_types TEXT NOT NULL DEFAULT '[]';
+
+CREATE TABLE IF NOT EXISTS uploads(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    form_id INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    name TEXT NOT NULL,
+    mime TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BLOB,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS uploads_form_id ON uploads(form_id);
@@ -0,0 +1,9 @@
+-- Options of the choice fields, `select`, `radio` and `checkboxes`, as an
+-- ordered JSON list of objects with a `value` and a `label`. A `checkboxes`
+-- field stores the values chosen as a JSON array. A `radio` field used to be
+-- a single boolean, which is what a `checkbox` is, so those fields become
+-- one and keep their column.
+
+ALTER TABLE fields ADD COLUMN options TEXT NOT NULL DEFAULT '[]';
+
+UPDATE fields SET datatype = 'checkbox' WHERE datatype = 'radio';
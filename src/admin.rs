@@ -0,0 +1,470 @@
+use actix_web::{get, post, web, Result, error, Error, Responder,
+    HttpResponse, http::header};
+use actix_web_lab::respond::Html;
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use serde::Deserialize;
+use std::collections::HashMap;
+use log::{debug, error};
+use crate::models::{Form, Field, NewField, Choice, Constraints, Notification, Protection, Webhook,
+    is_valid_field_name, is_valid_form_name, validate_definition, default_template,
+    DATATYPES, DEFAULT_SUBJECT};
+use crate::http::{auth::Admin, csrf};
+
+/// Most leading zero bits of a proof of work, around a minute of a slow phone.
+const MAX_POW_DIFFICULTY: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+pub struct FormSchema{
+    name: Option<String>,
+    title: String,
+    instructions: String,
+    template: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FieldSchema{
+    name: Option<String>,
+    datatype: String,
+    label: String,
+    placeholder: String,
+    required: Option<String>,
+    unique: Option<String>,
+    min_value: Option<String>,
+    max_value: Option<String>,
+    min_length: Option<String>,
+    max_length: Option<String>,
+    pattern: Option<String>,
+    allowed: Option<String>,
+    max_size: Option<String>,
+    mime_types: Option<String>,
+    options: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationSchema{
+    enabled: Option<String>,
+    recipients: String,
+    subject: String,
+    include_answers: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProtectionSchema{
+    honeypot: Option<String>,
+    honeypot_name: String,
+    min_fill_seconds: i64,
+    rate_limit: i64,
+    rate_window: i64,
+    pow_difficulty: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Message{
+    pub error: Option<String>,
+}
+
+fn not_empty(value: &Option<String>) -> Option<String>{
+    value.as_ref()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn split_commas(value: &Option<String>) -> Vec<String>{
+    not_empty(value)
+        .map(|values| values.split(',')
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Options written one per line as `value=Label`, or just `value` when the
+/// label is the same.
+fn parse_options(value: &Option<String>) -> Vec<Choice>{
+    value.as_deref()
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('='){
+            Some((value, label)) => Choice{
+                value: value.trim().to_string(),
+                label: label.trim().to_string(),
+            },
+            None => Choice{
+                value: line.to_string(),
+                label: line.to_string(),
+            },
+        })
+        .collect()
+}
+
+impl FieldSchema{
+    fn constraints(&self) -> Constraints{
+        Constraints{
+            min_value: not_empty(&self.min_value),
+            max_value: not_empty(&self.max_value),
+            min_length: not_empty(&self.min_length)
+                .and_then(|value| value.parse().ok()),
+            max_length: not_empty(&self.max_length)
+                .and_then(|value| value.parse().ok()),
+            pattern: not_empty(&self.pattern),
+            allowed: split_commas(&self.allowed),
+            // Sizes are asked in kilobytes
+            max_size: not_empty(&self.max_size)
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(|kilobytes| kilobytes * 1024),
+            mime_types: split_commas(&self.mime_types)
+                .into_iter()
+                .map(|mime| mime.to_lowercase())
+                .collect(),
+            options: parse_options(&self.options),
+        }
+    }
+}
+
+pub(crate) fn redirect(location: &str) -> HttpResponse{
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+pub(crate) fn redirect_with_error(location: &str, message: &str) -> HttpResponse{
+    let query = serde_urlencoded::to_string([("error", message)])
+        .unwrap_or_default();
+    redirect(&format!("{}?{}", location, query))
+}
+
+pub(crate) fn render(template: &Tera, name: &str, ctx: &tera::Context) -> Result<String, Error>{
+    debug!("Template: {}", name);
+    template.render(name, ctx)
+        .map_err(|e| {
+            error!("Template error: {}", e);
+            error::ErrorInternalServerError("Template error")
+        })
+}
+
+/// Checks that a form can be rendered with the template `name`, that is, it
+/// is one of those loaded.
+pub(crate) fn is_template(template: &Tera, name: &str) -> bool{
+    template.get_template_names().any(|known| known == name)
+}
+
+pub(crate) async fn read_form(pool: &SqlitePool, id: i64) -> Result<Form, Error>{
+    Form::read(pool, id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Form not found"))
+}
+
+async fn read_field(pool: &SqlitePool, id: i64) -> Result<Field, Error>{
+    Field::read(pool, id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Field not found"))
+}
+
+#[get("")]
+pub async fn get_forms(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        message: web::Query<Message>) -> Result<impl Responder, Error>{
+    let forms = Form::read_all(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("forms", &forms);
+    ctx.insert("error", &message.error);
+    Ok(Html(render(&template, "admin/forms.html", &ctx)?))
+}
+
+#[post("/forms")]
+pub async fn post_forms(_admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        data: web::Form<FormSchema>) -> Result<HttpResponse, Error>{
+    let name = data.name.clone().unwrap_or_default().trim().to_string();
+    if !is_valid_form_name(&name){
+        return Ok(redirect_with_error("/admin", &format!(
+            "The name {} is not valid. Use lowercase letters, digits and _", name)));
+    }
+    if Form::read_by_name(pool.get_ref(), &name).await.is_ok(){
+        return Ok(redirect_with_error("/admin", &format!(
+            "There is already a form named {}", name)));
+    }
+    let deleted = Form::read_deleted(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    if deleted.iter().any(|form| form.name == name){
+        return Ok(redirect_with_error("/admin", &format!(
+            "The form {} is in the trash. Restore or purge it first", name)));
+    }
+    let form_template = not_empty(&data.template).unwrap_or_else(default_template);
+    if !is_template(&template, &form_template){
+        return Ok(redirect_with_error("/admin", &format!(
+            "The template {} does not exist", form_template)));
+    }
+    match Form::new(pool.get_ref(), name.clone(), data.title.clone(),
+            data.instructions.clone(), form_template).await{
+        Ok(form) => Ok(redirect(&format!("/admin/forms/{}", form.id))),
+        Err(e) => {
+            error!("Can not create form {}: {}", name, e);
+            Ok(redirect_with_error("/admin", &format!(
+                "Can not create the form {}", name)))
+        },
+    }
+}
+
+#[get("/forms/{id}")]
+pub async fn get_form(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>, message: web::Query<Message>) -> Result<impl Responder, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let fields = form.get_fields(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let notification = Notification::read(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let webhooks = Webhook::read_by_form_id(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let protection = Protection::read(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("form", &form);
+    ctx.insert("fields", &fields);
+    ctx.insert("notification", &notification);
+    ctx.insert("webhooks", &webhooks);
+    ctx.insert("protection", &protection);
+    ctx.insert("datatypes", &DATATYPES);
+    ctx.insert("error", &message.error);
+    Ok(Html(render(&template, "admin/form.html", &ctx)?))
+}
+
+/// The form as the public will see it, shown inside its edition page.
+#[get("/forms/{id}/preview")]
+pub async fn get_preview(_admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>) -> Result<impl Responder, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let fields = form.get_fields(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let mut ctx = csrf::context();
+    ctx.insert("table", &form.name);
+    ctx.insert("action", &format!("/{}", form.name));
+    ctx.insert("title", &form.title);
+    ctx.insert("instructions", &form.instructions);
+    ctx.insert("fields", &fields);
+    ctx.insert("values", &HashMap::<String, String>::new());
+    ctx.insert("chosen", &HashMap::<String, Vec<String>>::new());
+    ctx.insert("errors", &HashMap::<String, String>::new());
+    ctx.insert("preview", &true);
+    Ok(Html(render(&template, &form.template, &ctx)?))
+}
+
+#[post("/forms/{id}")]
+pub async fn post_form(_admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>, data: web::Form<FormSchema>) -> Result<HttpResponse, Error>{
+    let mut form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", form.id);
+    form.title = data.title.clone();
+    form.instructions = data.instructions.clone();
+    form.template = not_empty(&data.template).unwrap_or_else(default_template);
+    if !is_template(&template, &form.template){
+        return Ok(redirect_with_error(&location, &format!(
+            "The template {} does not exist", form.template)));
+    }
+    match form.save(pool.get_ref()).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not save form {}: {}", form.name, e);
+            Ok(redirect_with_error(&location, "Can not save the form"))
+        },
+    }
+}
+
+#[post("/forms/{id}/delete")]
+pub async fn delete_form(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    Form::remove(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    debug!("Form {} moved to the trash", form.name);
+    Ok(redirect("/admin"))
+}
+
+/// Who is told by email of the new submissions of the form.
+#[post("/forms/{id}/notification")]
+pub async fn post_notification(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<NotificationSchema>) -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", form.id);
+    let mut notification = Notification::read(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    notification.enabled = data.enabled.is_some();
+    notification.recipients = data.recipients.trim().to_string();
+    notification.subject = not_empty(&Some(data.subject.clone()))
+        .unwrap_or_else(|| DEFAULT_SUBJECT.to_string());
+    notification.include_answers = data.include_answers.is_some();
+    let invalid: Vec<String> = notification.get_recipients()
+        .into_iter()
+        .filter(|recipient| recipient.parse::<lettre::message::Mailbox>().is_err())
+        .collect();
+    if !invalid.is_empty(){
+        return Ok(redirect_with_error(&location, &format!(
+            "Not valid recipients: {}", invalid.join(", "))));
+    }
+    if Tera::default().add_raw_template("subject", &notification.subject).is_err(){
+        return Ok(redirect_with_error(&location, "The subject is not a valid template"));
+    }
+    match notification.save(pool.get_ref()).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not save notification of {}: {}", form.name, e);
+            Ok(redirect_with_error(&location, "Can not save the notification"))
+        },
+    }
+}
+
+/// How the public form is protected against bots.
+#[post("/forms/{id}/protection")]
+pub async fn post_protection(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<ProtectionSchema>) -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", form.id);
+    let fields = form.get_fields(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let honeypot_name = data.honeypot_name.trim().to_string();
+    if !is_valid_field_name(&honeypot_name) ||
+            fields.iter().any(|field| field.get_name() == honeypot_name){
+        return Ok(redirect_with_error(&location, &format!(
+            "The name {} can not be used for the honeypot", honeypot_name)));
+    }
+    if data.min_fill_seconds < 0 || data.rate_limit < 0 || data.rate_window < 1{
+        return Ok(redirect_with_error(&location, "The limits must be positive"));
+    }
+    if !(0..=MAX_POW_DIFFICULTY).contains(&data.pow_difficulty){
+        return Ok(redirect_with_error(&location, &format!(
+            "The difficulty must be between 0 and {}", MAX_POW_DIFFICULTY)));
+    }
+    let mut protection = Protection::read(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    protection.honeypot = data.honeypot.is_some();
+    protection.honeypot_name = honeypot_name;
+    protection.min_fill_seconds = data.min_fill_seconds;
+    protection.rate_limit = data.rate_limit;
+    protection.rate_window = data.rate_window;
+    protection.pow_difficulty = data.pow_difficulty;
+    match protection.save(pool.get_ref()).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not save protection of {}: {}", form.name, e);
+            Ok(redirect_with_error(&location, "Can not save the protection"))
+        },
+    }
+}
+
+#[post("/forms/{id}/fields")]
+pub async fn post_fields(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<FieldSchema>) -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", form.id);
+    let name = data.name.clone().unwrap_or_default().trim().to_string();
+    if !is_valid_field_name(&name){
+        return Ok(redirect_with_error(&location, &format!(
+            "The name {} is not valid. Use lowercase letters, digits and _", name)));
+    }
+    // fields in the trash still hold their names
+    let fields = Field::read_all_by_form_id(pool.get_ref(), form.id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    match fields.iter().find(|field| field.get_name() == name){
+        Some(field) if field.get_deleted_at().is_some() =>
+            return Ok(redirect_with_error(&location, &format!(
+                "The field {} is in the trash. Restore or purge it first", name))),
+        Some(_) => return Ok(redirect_with_error(&location, &format!(
+            "There is already a field named {}", name))),
+        None => {},
+    }
+    if let Err(message) = validate_definition(&data.datatype, &data.constraints()){
+        return Ok(redirect_with_error(&location, &message));
+    }
+    let field = NewField{
+        name: name.clone(),
+        datatype: data.datatype.clone(),
+        label: data.label.clone(),
+        placeholder: data.placeholder.clone(),
+        required: data.required.is_some(),
+        unique: data.unique.is_some(),
+        constraints: data.constraints(),
+    };
+    match Field::new(pool.get_ref(), form.id, field).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not create field {}: {}", name, e);
+            Ok(redirect_with_error(&location, &format!(
+                "Can not create the field {}", name)))
+        },
+    }
+}
+
+#[post("/fields/{id}")]
+pub async fn post_field(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<FieldSchema>) -> Result<HttpResponse, Error>{
+    let mut field = read_field(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", field.get_form_id());
+    if let Err(message) = validate_definition(&data.datatype, &data.constraints()){
+        return Ok(redirect_with_error(&location, &message));
+    }
+    field.set_datatype(data.datatype.clone());
+    field.set_label(data.label.clone());
+    field.set_placeholder(data.placeholder.clone());
+    field.set_required(data.required.is_some());
+    field.set_unique(data.unique.is_some());
+    field.set_constraints(data.constraints());
+    match field.save(pool.get_ref()).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not save field {}: {}", field.get_name(), e);
+            Ok(redirect_with_error(&location, &format!(
+                "Can not save the field {}", field.get_name())))
+        },
+    }
+}
+
+#[post("/fields/{id}/up")]
+pub async fn move_field_up(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let field = read_field(pool.get_ref(), path.into_inner()).await?;
+    Field::move_by(pool.get_ref(), field.get_id(), -1)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    Ok(redirect(&format!("/admin/forms/{}", field.get_form_id())))
+}
+
+#[post("/fields/{id}/down")]
+pub async fn move_field_down(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let field = read_field(pool.get_ref(), path.into_inner()).await?;
+    Field::move_by(pool.get_ref(), field.get_id(), 1)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    Ok(redirect(&format!("/admin/forms/{}", field.get_form_id())))
+}
+
+#[post("/fields/{id}/delete")]
+pub async fn delete_field(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let field = read_field(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", field.get_form_id());
+    match Field::remove(pool.get_ref(), field.get_id()).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not delete field {}: {}", field.get_name(), e);
+            Ok(redirect_with_error(&location, &format!(
+                "Can not delete the field {}", field.get_name())))
+        },
+    }
+}
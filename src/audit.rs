@@ -0,0 +1,84 @@
+use actix_web::{get, web, Result, error, Error, Responder, HttpResponse};
+use actix_web_lab::respond::Html;
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use std::collections::HashMap;
+use log::error;
+
+use crate::models::{Audit, AuditFilter};
+use crate::admin::render;
+use crate::export::{attachment, csv_line};
+use crate::http::{auth::Admin, csrf};
+
+fn database_error(e: crate::models::Error) -> Error{
+    error!("Database error: {}", e);
+    error::ErrorInternalServerError("Database error")
+}
+
+/// Link to a page of the audit log keeping the filters.
+fn page_url(query: &str, page: i64, per_page: i64) -> String{
+    let separator = if query.is_empty() { "" } else { "&" };
+    format!("/admin/audit?{}{}page={}&per_page={}", query, separator, page, per_page)
+}
+
+/// Changes made to forms, fields, submissions, users and settings, the
+/// newest first.
+#[get("/audit")]
+pub async fn get_audit(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        params: web::Query<HashMap<String, String>>) -> Result<impl Responder, Error>{
+    let filter = AuditFilter::from_query(&params);
+    let per_page = params.get("per_page")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(25)
+        .clamp(1, 500);
+    let total = Audit::count(pool.get_ref(), &filter)
+        .await
+        .map_err(database_error)?;
+    let pages = ((total + per_page - 1) / per_page).max(1);
+    let page = params.get("page")
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(1)
+        .clamp(1, pages);
+    let entries = Audit::read_page(pool.get_ref(), &filter, Some(per_page),
+            (page - 1) * per_page)
+        .await
+        .map_err(database_error)?;
+    let query = filter.to_query();
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("entries", &entries);
+    ctx.insert("filter", &filter);
+    ctx.insert("query", &query);
+    ctx.insert("total", &total);
+    ctx.insert("page", &page);
+    ctx.insert("pages", &pages);
+    ctx.insert("per_page", &per_page);
+    if page > 1{
+        ctx.insert("previous_url", &page_url(&query, page - 1, per_page));
+    }
+    if page < pages{
+        ctx.insert("next_url", &page_url(&query, page + 1, per_page));
+    }
+    Ok(Html(render(&template, "admin/audit.html", &ctx)?))
+}
+
+/// The entries of the audit log that pass the filters as a CSV file.
+#[get("/audit/export.csv")]
+pub async fn get_audit_csv(_admin: Admin, pool: web::Data<SqlitePool>,
+        params: web::Query<HashMap<String, String>>) -> Result<HttpResponse, Error>{
+    let filter = AuditFilter::from_query(&params);
+    let entries = Audit::read_page(pool.get_ref(), &filter, None, 0)
+        .await
+        .map_err(database_error)?;
+    let mut content = csv_line(&["id", "created_at", "actor", "action", "entity",
+            "entity_id", "before", "after"].map(|header| header.to_string()));
+    for entry in entries{
+        content.push_str(&csv_line(&[entry.id.to_string(), entry.created_at.to_rfc3339(),
+            entry.actor, entry.action, entry.entity, entry.entity_id,
+            entry.before.unwrap_or_default(), entry.after.unwrap_or_default()]));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(attachment("audit", "csv"))
+        .body(content))
+}
@@ -0,0 +1,66 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use crate::models::Constraints;
+
+fn default_template() -> String{
+    "index.html".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TableField{
+    pub name: String,
+    pub datatype: String,
+    pub label: String,
+    pub placeholder: String,
+    pub required: bool,
+    pub unique: bool,
+    #[serde(flatten)]
+    pub constraints: Constraints,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Table{
+    #[serde(default = "default_template")]
+    pub template: String,
+    pub title: String,
+    pub instructions: String,
+    pub fields: Vec<TableField>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Configuration{
+    log_level: String,
+    db_url: String,
+    port: u16,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub secure_cookies: bool,
+    #[serde(default)]
+    pub proxy_header: Option<String>,
+    /// Directory of the uploaded files. Without it they are kept in the
+    /// database.
+    #[serde(default)]
+    pub upload_dir: Option<String>,
+    #[serde(default)]
+    pub tables: HashMap<String, Table>,
+}
+
+impl Configuration{
+    pub fn new(content: &str) -> Result<Configuration, serde_yaml::Error>{
+        serde_yaml::from_str(content)
+    }
+
+    pub fn get_log_level(&self) -> &str{
+        &self.log_level
+    }
+
+    pub fn get_db_url(&self) -> &str{
+        &self.db_url
+    }
+
+    pub fn get_port(&self) -> u16{
+        self.port
+    }
+}
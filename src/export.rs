@@ -0,0 +1,426 @@
+use actix_web::{get, web, Error, HttpResponse, error, http::header,
+    web::Bytes};
+use futures_util::stream;
+use sqlx::sqlite::SqlitePool;
+use serde_json::Value;
+use std::collections::HashMap;
+use log::{debug, error};
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook, Worksheet, XlsxError};
+
+use crate::models::{Form, Field, Filter};
+use crate::http::auth::Admin;
+
+/// Rows read from the database for every chunk of an export.
+const CHUNK: i64 = 500;
+
+/// Metadata of the submissions exported before their fields.
+const METADATA: [&str; 5] = ["id", "uuid", "submitted_at", "ip", "user_agent"];
+
+/// Escapes a value for a CSV file following RFC 4180. Values that a
+/// spreadsheet would take as a formula are prefixed with a quote, but not
+/// numbers, so that negative ones are still numbers.
+fn csv_field(value: &str) -> String{
+    let is_number = value.parse::<f64>().map(f64::is_finite).unwrap_or(false);
+    let value = if !is_number && value.starts_with(['=', '+', '-', '@', '\t', '\r']){
+        format!("'{}", value)
+    }else{
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']){
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }else{
+        value
+    }
+}
+
+pub(crate) fn csv_line(values: &[String]) -> String{
+    let fields: Vec<String> = values.iter()
+        .map(|value| csv_field(value))
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+fn to_text(value: &Value) -> String{
+    match value{
+        Value::Null => "".to_string(),
+        Value::String(value) => value.to_string(),
+        // The options chosen in a `checkboxes` field
+        Value::Array(values) => values.iter()
+            .map(to_text)
+            .collect::<Vec<String>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+/// Everything needed to read the next chunk of an export.
+struct Cursor{
+    pool: web::Data<SqlitePool>,
+    form: Form,
+    fields: Vec<Field>,
+    filter: Filter,
+    offset: i64,
+    done: bool,
+}
+
+impl Cursor{
+    async fn next_rows(&mut self) -> Result<Vec<Row>, Error>{
+        let rows = self.form.read_rows(self.pool.get_ref(), &self.fields, &self.filter,
+                Some(CHUNK), self.offset)
+            .await
+            .map_err(|e| {
+                error!("Can not export {}: {}", self.form.name, e);
+                error::ErrorInternalServerError("Database error")
+            })?;
+        self.offset += rows.len() as i64;
+        self.done = (rows.len() as i64) < CHUNK;
+        Ok(rows.iter()
+            .map(|row| Form::row_to_json(&self.fields, row))
+            .collect())
+    }
+}
+
+/// Reads the form and its fields, and the filter of the request.
+async fn prepare(pool: &web::Data<SqlitePool>, table_name: &str,
+        params: &HashMap<String, String>) -> Result<Cursor, Error>{
+    let form = Form::read_by_name(pool.get_ref(), table_name)
+        .await
+        .map_err(|_| error::ErrorNotFound("Form not found"))?;
+    let fields = form.get_fields(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let filter = Filter::from_query(&fields, params);
+    Ok(Cursor{
+        pool: pool.clone(),
+        form,
+        fields,
+        filter,
+        offset: 0,
+        done: false,
+    })
+}
+
+pub(crate) fn attachment(name: &str, extension: &str) -> (header::HeaderName, String){
+    (header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}.{}\"", name, extension))
+}
+
+type Row = serde_json::Map<String, Value>;
+
+/// Streams `head` followed by every submission of the cursor formatted by
+/// `format`, reading them from the database in chunks.
+fn stream_rows(cursor: Cursor, head: String, format: fn(&[Field], &Row) -> String)
+        -> impl futures_util::Stream<Item = Result<Bytes, Error>>{
+    stream::unfold((cursor, Some(head)), move |(mut cursor, head)| async move {
+        if let Some(head) = head{
+            return Some((Ok(Bytes::from(head)), (cursor, None)));
+        }
+        if cursor.done{
+            return None;
+        }
+        match cursor.next_rows().await{
+            Ok(rows) => {
+                let chunk: String = rows.iter()
+                    .map(|row| format(&cursor.fields, row))
+                    .collect();
+                Some((Ok(Bytes::from(chunk)), (cursor, None)))
+            },
+            Err(e) => {
+                cursor.done = true;
+                Some((Err(e), (cursor, None)))
+            },
+        }
+    })
+}
+
+/// The values of a submission as CSV, with the labels of the options chosen
+/// in choice fields.
+fn csv_row(fields: &[Field], row: &Row) -> String{
+    let mut values: Vec<String> = METADATA.iter()
+        .map(|name| row.get(*name).map(to_text).unwrap_or_default())
+        .collect();
+    values.extend(fields.iter().map(|field| row.get(field.get_name())
+        .map(|value| field.to_label(value))
+        .unwrap_or_default()));
+    csv_line(&values)
+}
+
+fn typed_row(fields: &[Field], row: &Row) -> Row{
+    let mut typed = Row::new();
+    for name in METADATA{
+        typed.insert(name.to_string(), row.get(name).cloned().unwrap_or(Value::Null));
+    }
+    for field in fields{
+        let value = row.get(field.get_name()).unwrap_or(&Value::Null);
+        typed.insert(field.get_name().to_string(), field.to_typed(value));
+    }
+    typed
+}
+
+/// The typed values of a submission for a spreadsheet, with the labels of
+/// the options chosen in choice fields.
+fn labelled_row(fields: &[Field], row: &Row) -> Row{
+    let mut labelled = typed_row(fields, row);
+    for field in fields.iter().filter(|field| field.has_options()){
+        match row.get(field.get_name()){
+            Some(Value::Null) | None => {},
+            Some(value) => {
+                labelled.insert(field.get_name().to_string(), Value::from(field.to_label(value)));
+            },
+        }
+    }
+    labelled
+}
+
+fn jsonl_row(fields: &[Field], row: &Row) -> String{
+    format!("{}\n", Value::Object(typed_row(fields, row)))
+}
+
+/// All the submissions of a form that pass the filters of the results view,
+/// as CSV. The header has the labels of the fields, or their names with
+/// `header=names`, and `bom=true` starts the file with a UTF-8 BOM for
+/// spreadsheets that need it.
+#[get("/{table}/export.csv")]
+pub async fn get_csv(_admin: Admin, pool: web::Data<SqlitePool>,
+        table_path: web::Path<String>, params: web::Query<HashMap<String, String>>)
+        -> Result<HttpResponse, Error>{
+    let cursor = prepare(&pool, &table_path, &params).await?;
+    debug!("Exporting {} as CSV", cursor.form.name);
+    let use_names = params.get("header").map(|value| value == "names").unwrap_or(false);
+    let bom = params.get("bom").map(|value| value == "true" || value == "1").unwrap_or(false);
+    let mut head = String::new();
+    if bom{
+        head.push('\u{feff}');
+    }
+    head.push_str(&csv_line(&header(&cursor.fields, use_names)));
+    let name = cursor.form.name.clone();
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(attachment(&name, "csv"))
+        .streaming(stream_rows(cursor, head, csv_row)))
+}
+
+/// The same submissions as the CSV export, as JSON Lines: one object per
+/// submission with typed values. Choice fields keep the values of their
+/// options, as an array for `checkboxes`.
+#[get("/{table}/export.jsonl")]
+pub async fn get_jsonl(_admin: Admin, pool: web::Data<SqlitePool>,
+        table_path: web::Path<String>, params: web::Query<HashMap<String, String>>)
+        -> Result<HttpResponse, Error>{
+    let cursor = prepare(&pool, &table_path, &params).await?;
+    debug!("Exporting {} as JSON Lines", cursor.form.name);
+    let name = cursor.form.name.clone();
+    Ok(HttpResponse::Ok()
+        .content_type("application/jsonl; charset=utf-8")
+        .insert_header(attachment(&name, "jsonl"))
+        .streaming(stream_rows(cursor, String::new(), jsonl_row)))
+}
+
+fn header(fields: &[Field], use_names: bool) -> Vec<String>{
+    let mut header: Vec<String> = METADATA.iter()
+        .map(|name| name.to_string())
+        .collect();
+    header.extend(fields.iter().map(|field| {
+        if use_names || field.get_label().is_empty(){
+            field.get_name().to_string()
+        }else{
+            field.get_label().to_string()
+        }
+    }));
+    header
+}
+
+fn write_cell(worksheet: &mut Worksheet, row: u32, col: u16, field: Option<&Field>,
+        value: &Value, formats: &HashMap<&str, Format>) -> Result<(), XlsxError>{
+    let datatype = field.map(|field| field.get_datatype()).unwrap_or("number");
+    match value{
+        Value::Null => {},
+        Value::Bool(boolean) => {
+            worksheet.write_boolean(row, col, *boolean)?;
+        },
+        Value::Number(number) => {
+            worksheet.write_number(row, col, number.as_f64().unwrap_or_default())?;
+        },
+        Value::String(text) => {
+            let datetime = match formats.get(datatype){
+                Some(format) => ExcelDateTime::parse_from_str(text)
+                    .ok()
+                    .map(|datetime| (datetime, format)),
+                None => None,
+            };
+            match datetime{
+                Some((datetime, format)) => {
+                    worksheet.write_datetime_with_format(row, col, &datetime, format)?;
+                },
+                None => {
+                    worksheet.write_string(row, col, text)?;
+                },
+            }
+        },
+        value => {
+            worksheet.write_string(row, col, to_text(value))?;
+        },
+    }
+    Ok(())
+}
+
+/// The same submissions as the CSV export, as an Excel workbook with typed
+/// cells and the header row frozen.
+#[get("/{table}/export.xlsx")]
+pub async fn get_xlsx(_admin: Admin, pool: web::Data<SqlitePool>,
+        table_path: web::Path<String>, params: web::Query<HashMap<String, String>>)
+        -> Result<HttpResponse, Error>{
+    let mut cursor = prepare(&pool, &table_path, &params).await?;
+    debug!("Exporting {} as XLSX", cursor.form.name);
+    let use_names = params.get("header").map(|value| value == "names").unwrap_or(false);
+    let name = cursor.form.name.clone();
+    let xlsx_error = |e: XlsxError| {
+        error!("Can not export {} as XLSX: {}", name, e);
+        error::ErrorInternalServerError("Export error")
+    };
+    let mut formats = HashMap::new();
+    formats.insert("date", Format::new().set_num_format("yyyy-mm-dd"));
+    formats.insert("datetime-local", Format::new().set_num_format("yyyy-mm-dd hh:mm"));
+    formats.insert("time", Format::new().set_num_format("hh:mm"));
+    let bold = Format::new().set_bold();
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name(name.chars().take(31).collect::<String>())
+        .map_err(xlsx_error)?;
+    for (col, title) in header(&cursor.fields, use_names).iter().enumerate(){
+        worksheet.write_string_with_format(0, col as u16, title, &bold)
+            .map_err(xlsx_error)?;
+    }
+    worksheet.set_freeze_panes(1, 0)
+        .map_err(xlsx_error)?;
+    // Every chunk goes into the worksheet as it is read
+    let mut line = 0;
+    while !cursor.done{
+        for row in cursor.next_rows().await?{
+            let typed = labelled_row(&cursor.fields, &row);
+            line += 1;
+            for (col, name) in METADATA.iter().enumerate(){
+                let value = typed.get(*name).unwrap_or(&Value::Null);
+                write_cell(worksheet, line, col as u16, None, value, &formats)
+                    .map_err(xlsx_error)?;
+            }
+            for (col, field) in cursor.fields.iter().enumerate(){
+                let value = typed.get(field.get_name()).unwrap_or(&Value::Null);
+                write_cell(worksheet, line, (METADATA.len() + col) as u16, Some(field), value,
+                        &formats)
+                    .map_err(xlsx_error)?;
+            }
+        }
+    }
+    let buffer = workbook.save_to_buffer()
+        .map_err(xlsx_error)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+        .insert_header(attachment(&name, "xlsx"))
+        .body(buffer))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn csv_field_keeps_plain_values(){
+        assert_eq!(csv_field("hello"), "hello");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("3.5"), "3.5");
+    }
+
+    #[test]
+    fn csv_field_quotes_special_characters(){
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("two\r\nlines"), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn csv_field_escapes_formulas(){
+        assert_eq!(csv_field("=SUM(A1:A2)"), "'=SUM(A1:A2)");
+        assert_eq!(csv_field("+A1"), "'+A1");
+        assert_eq!(csv_field("-1+A1"), "'-1+A1");
+        assert_eq!(csv_field("-inf"), "'-inf");
+        assert_eq!(csv_field("@cmd"), "'@cmd");
+        assert_eq!(csv_field("\tx"), "'\tx");
+        assert_eq!(csv_field("=HYPERLINK(\"http://a\",\"b\")"),
+            "\"'=HYPERLINK(\"\"http://a\"\",\"\"b\"\")\"");
+        assert_eq!(csv_field("a=b"), "a=b");
+        // Numbers are not formulas
+        assert_eq!(csv_field("-1"), "-1");
+        assert_eq!(csv_field("-2.5"), "-2.5");
+        assert_eq!(csv_field("+3"), "+3");
+    }
+
+    #[test]
+    fn typed_row_types_the_values_of_the_fields(){
+        let fields = [("age", "number"), ("score", "range"), ("agree", "checkbox"),
+                ("born", "date"), ("meeting", "datetime-local"), ("name", "text")]
+            .iter()
+            .map(|(name, datatype)| Field::sample(name, datatype, false, Default::default()))
+            .collect::<Vec<Field>>();
+        let row: Row = serde_json::from_value(serde_json::json!({
+            "id": 1, "uuid": "abc", "age": "42", "score": "-2.5", "agree": "on",
+            "born": "2024-02-29", "meeting": "2024-02-29T10:30", "name": "12",
+        })).unwrap();
+        let typed = typed_row(&fields, &row);
+        assert_eq!(typed["id"], Value::from(1));
+        assert_eq!(typed["submitted_at"], Value::Null);
+        assert_eq!(typed["age"], Value::from(42));
+        assert_eq!(typed["score"], Value::from(-2.5));
+        assert_eq!(typed["agree"], Value::Bool(true));
+        assert_eq!(typed["born"], Value::from("2024-02-29"));
+        assert_eq!(typed["meeting"], Value::from("2024-02-29T10:30"));
+        assert_eq!(typed["name"], Value::from("12"));
+        // The dates are ISO 8601, so they are written as dates in the XLSX
+        for name in ["born", "meeting"]{
+            assert!(ExcelDateTime::parse_from_str(typed[name].as_str().unwrap()).is_ok());
+        }
+    }
+
+    fn choices() -> Vec<Field>{
+        let constraints = crate::models::Constraints{
+            options: [("es", "Spain"), ("pt", "Portugal")].iter()
+                .map(|(value, label)| crate::models::Choice{
+                    value: value.to_string(),
+                    label: label.to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        ["select", "checkboxes"].iter()
+            .map(|datatype| Field::sample(datatype, datatype, false, constraints.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_row_writes_the_labels_of_the_options(){
+        let row: Row = serde_json::from_value(serde_json::json!({
+            "id": 1, "select": "pt", "checkboxes": "[\"es\",\"pt\"]",
+        })).unwrap();
+        assert!(csv_row(&choices(), &row).ends_with(",Portugal,\"Spain, Portugal\"\r\n"));
+    }
+
+    #[test]
+    fn labelled_row_writes_the_labels_of_the_options(){
+        let fields = choices();
+        let row: Row = serde_json::from_value(serde_json::json!({
+            "id": 1, "select": "es", "checkboxes": null,
+        })).unwrap();
+        let labelled = labelled_row(&fields, &row);
+        assert_eq!(labelled["select"], Value::from("Spain"));
+        assert_eq!(labelled["checkboxes"], Value::Null);
+        // JSON Lines keep the values
+        assert_eq!(typed_row(&fields, &row)["select"], Value::from("es"));
+    }
+
+    #[test]
+    fn csv_line_ends_with_crlf(){
+        let values = ["a".to_string(), "b,c".to_string()];
+        assert_eq!(csv_line(&values), "a,\"b,c\"\r\n");
+    }
+}
@@ -0,0 +1,177 @@
+use std::{future::{self, Future, Ready}, pin::Pin};
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error,
+    http::{header, Method, StatusCode},
+    web,
+    Error,
+    FromRequest,
+    HttpMessage,
+    HttpRequest,
+    HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use actix_session::SessionExt;
+use chrono::{TimeZone, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use log::debug;
+
+use crate::models::{self, audit::as_actor, Param, Token, TokenClaims, User};
+
+/// Key of the session where the id of the logged user is stored.
+pub const USER_ID: &str = "user_id";
+
+/// Sends the browser to the login page, coming back to `path` once logged.
+fn to_login(path: &str) -> Error{
+    let query = serde_urlencoded::to_string([("next", path)])
+        .unwrap_or_default();
+    let response = HttpResponse::Found()
+        .insert_header((header::LOCATION, format!("/login?{}", query)))
+        .finish();
+    error::InternalError::from_response("Login required", response).into()
+}
+
+/// An active user with the `Admin` role, logged in through the session.
+/// Routes that take it as an argument are only available to administrators.
+pub struct Admin(pub User);
+
+impl FromRequest for Admin{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future{
+        let req = req.clone();
+        Box::pin(async move {
+            let path = req.uri().to_string();
+            let pool = req.app_data::<web::Data<SqlitePool>>()
+                .ok_or_else(|| error::ErrorInternalServerError("Database error"))?;
+            let user_id = match req.get_session().get::<i64>(USER_ID)?{
+                Some(user_id) => user_id,
+                None => return Err(to_login(&path)),
+            };
+            let user = match User::read(pool.get_ref(), user_id).await{
+                Ok(user) => user,
+                Err(e) => {
+                    debug!("User {} not found: {}", user_id, e);
+                    req.get_session().purge();
+                    return Err(to_login(&path));
+                },
+            };
+            if user.is_admin(){
+                Ok(Admin(user))
+            }else{
+                Err(error::ErrorForbidden("Forbidden"))
+            }
+        })
+    }
+}
+
+/// An error for API clients, as a JSON body with the message.
+pub fn json_error(status: StatusCode, message: &str) -> Error{
+    let response = HttpResponse::build(status)
+        .json(json!({"error": message}));
+    error::InternalError::from_response(message.to_string(), response).into()
+}
+
+/// Signs a token for the user that expires after `jwt_expires_in` seconds.
+pub async fn issue_token(pool: &SqlitePool, user: &User) -> Result<(String, i64), models::Error>{
+    let expires_in = Param::get_jwt_expires_in(pool).await;
+    let now = Utc::now().timestamp();
+    let claims = TokenClaims{
+        sub: user.name.clone(),
+        iat: now as usize,
+        exp: (now + expires_in) as usize,
+        jti: uuid::Uuid::new_v4().to_string(),
+    };
+    let secret = Param::get_secret(pool).await;
+    let token = encode(&Header::default(), &claims,
+        &EncodingKey::from_secret(secret.as_bytes()))?;
+    Ok((token, expires_in))
+}
+
+/// Checks the signature, expiration and revocation of a token.
+pub async fn verify_token(pool: &SqlitePool, token: &str) -> Result<TokenClaims, models::Error>{
+    let secret = Param::get_secret(pool).await;
+    let claims = decode::<TokenClaims>(token,
+        &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())?
+        .claims;
+    if Token::is_revoked(pool, &claims.jti).await?{
+        return Err("Token revoked".into());
+    }
+    Ok(claims)
+}
+
+/// Revokes a token until it expires.
+pub async fn revoke_token(pool: &SqlitePool, claims: &TokenClaims) -> Result<(), models::Error>{
+    let expires_at = Utc.timestamp_opt(claims.exp as i64, 0)
+        .single()
+        .unwrap_or_else(Utc::now);
+    Token::revoke(pool, &claims.jti, expires_at).await
+}
+
+/// Middleware for the API. Requires an `Authorization: Bearer` header with a
+/// valid token of an active user, available to the routes as `ApiUser`.
+pub async fn bearer(req: ServiceRequest, next: Next<impl MessageBody>)
+        -> Result<ServiceResponse<impl MessageBody>, Error>{
+    let pool = req.app_data::<web::Data<SqlitePool>>()
+        .ok_or_else(|| json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error"))?
+        .clone();
+    let token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|value| value.trim().to_string())
+        .ok_or_else(|| json_error(StatusCode::UNAUTHORIZED, "Missing bearer token"))?;
+    let claims = verify_token(pool.get_ref(), &token)
+        .await
+        .map_err(|e| {
+            debug!("Invalid token: {}", e);
+            json_error(StatusCode::UNAUTHORIZED, "Invalid token")
+        })?;
+    let user = match User::get_by_name(pool.get_ref(), &claims.sub).await{
+        Ok(user) if user.active => user,
+        _ => return Err(json_error(StatusCode::UNAUTHORIZED, "Invalid token")),
+    };
+    let actor = user.name.clone();
+    req.extensions_mut().insert(claims);
+    req.extensions_mut().insert(user);
+    as_actor(actor, next.call(req)).await
+}
+
+/// Middleware that makes the logged user of the session, or `anonymous`,
+/// the actor of the changes recorded in the audit log. Read only requests
+/// change nothing, so the session is not looked up for them.
+pub async fn audit_actor(req: ServiceRequest, next: Next<impl MessageBody>)
+        -> Result<ServiceResponse<impl MessageBody>, Error>{
+    if req.method() == Method::GET || req.method() == Method::HEAD{
+        return next.call(req).await;
+    }
+    let user_id = req.get_session().get::<i64>(USER_ID).ok().flatten();
+    let user = match (user_id, req.app_data::<web::Data<SqlitePool>>()){
+        (Some(user_id), Some(pool)) => User::read(pool.get_ref(), user_id).await.ok(),
+        _ => None,
+    };
+    let actor = user
+        .map(|user| user.name)
+        .unwrap_or_else(|| "anonymous".to_string());
+    as_actor(actor, next.call(req)).await
+}
+
+/// The user of the bearer token of an API request, set by `bearer`.
+pub struct ApiUser(pub User, pub TokenClaims);
+
+impl FromRequest for ApiUser{
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future{
+        let extensions = req.extensions();
+        future::ready(match (extensions.get::<User>(), extensions.get::<TokenClaims>()){
+            (Some(user), Some(claims)) => Ok(ApiUser(user.clone(), claims.clone())),
+            _ => Err(json_error(StatusCode::UNAUTHORIZED, "Missing bearer token")),
+        })
+    }
+}
@@ -0,0 +1,27 @@
+use actix_web::{HttpRequest, http::header};
+
+use crate::config::Configuration;
+use crate::models::Origin;
+
+/// Where a request comes from: the address of the client and its user agent.
+/// Behind a reverse proxy the address of the client is taken from the header
+/// named by `proxy_header` in `config.yml`, only to be set when every request
+/// goes through that proxy. Of a list like `X-Forwarded-For` the last address
+/// is used, as it is the one added by the proxy itself.
+pub fn origin(req: &HttpRequest, conf: &Configuration) -> Origin{
+    let forwarded = conf.proxy_header.as_ref()
+        .and_then(|name| req.headers().get(name.as_str()))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty());
+    let ip = forwarded.or_else(|| req.peer_addr()
+        .map(|address| address.ip().to_string()));
+    let user_agent = req.headers().get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    Origin{
+        ip,
+        user_agent,
+    }
+}
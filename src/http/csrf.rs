@@ -0,0 +1,277 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{self, PayloadError},
+    http::{header::HeaderMap, Method},
+    mime,
+    web,
+    Error,
+    HttpMessage,
+    HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use actix_session::SessionExt;
+use actix_multipart::Multipart;
+use futures_util::{stream, StreamExt};
+use log::{debug, error};
+
+use crate::uploads::MAX_MULTIPART_SIZE;
+
+/// Key of the session with the token of the client.
+const SESSION_KEY: &str = "csrf_token";
+
+/// Name of the hidden input with the token.
+pub const FIELD: &str = "_csrf";
+
+/// Longest token read from a multipart body, in bytes.
+const MAX_TOKEN_SIZE: usize = 256;
+
+/// Header with the token, for requests made from scripts.
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Paths left out of the check: the JSON API, authenticated with bearer
+/// tokens that browsers never send on their own.
+const EXEMPT: [&str; 1] = ["/api/"];
+
+tokio::task_local!{
+    /// Token of the session of the current request.
+    static TOKEN: String;
+}
+
+/// A context for templates with the token of the current request as
+/// `csrf_token`, to be sent back in the `_csrf` input of every form.
+pub fn context() -> tera::Context{
+    let mut ctx = tera::Context::new();
+    ctx.insert("csrf_token", &TOKEN.try_with(|token| token.clone()).unwrap_or_default());
+    ctx
+}
+
+/// Compares in constant time, so a secret can not be guessed byte by byte.
+pub fn same(a: &str, b: &str) -> bool{
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn forbidden() -> Error{
+    let response = HttpResponse::Forbidden()
+        .content_type("text/plain; charset=utf-8")
+        .body("The form has expired. Go back, reload it and send it again.");
+    error::InternalError::from_response("Invalid CSRF token", response).into()
+}
+
+/// The whole body of a multipart request, files included.
+async fn multipart_body(req: &mut ServiceRequest) -> Result<web::Bytes, Error>{
+    let mut payload = req.take_payload();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await{
+        let chunk = chunk?;
+        if (body.len() + chunk.len()) as i64 > MAX_MULTIPART_SIZE{
+            return Err(error::ErrorPayloadTooLarge("Form too large"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// The `_csrf` value of a multipart body. The parts after it are not read.
+async fn from_multipart(headers: &HeaderMap, body: web::Bytes) -> Option<String>{
+    let body = stream::once(async move { Ok::<_, PayloadError>(body) });
+    let mut multipart = Multipart::new(headers, body);
+    while let Some(part) = multipart.next().await{
+        let mut part = part.ok()?;
+        let disposition = part.content_disposition();
+        let is_token = disposition.get_name() == Some(FIELD)
+            && disposition.get_filename().is_none();
+        let mut value = Vec::new();
+        while let Some(chunk) = part.next().await{
+            let chunk = chunk.ok()?;
+            if is_token{
+                if value.len() + chunk.len() > MAX_TOKEN_SIZE{
+                    return None;
+                }
+                value.extend_from_slice(&chunk);
+            }
+        }
+        if is_token{
+            return String::from_utf8(value).ok();
+        }
+    }
+    None
+}
+
+/// Token sent with a request, from the header or the `_csrf` value of an
+/// url-encoded or multipart body. The body is read and put back for the
+/// route.
+async fn sent_token(req: &mut ServiceRequest) -> Result<Option<String>, Error>{
+    if let Some(token) = req.headers().get(HEADER).and_then(|value| value.to_str().ok()){
+        return Ok(Some(token.to_string()));
+    }
+    let content_type = req.content_type().to_string();
+    if content_type == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str(){
+        let body = req.extract::<web::Bytes>().await?;
+        let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+            .ok()
+            .and_then(|params| params.into_iter()
+                .find(|(key, _)| key == FIELD)
+                .map(|(_, value)| value));
+        req.set_payload(Payload::from(body));
+        Ok(token)
+    }else if content_type == mime::MULTIPART_FORM_DATA.essence_str(){
+        let body = multipart_body(req).await?;
+        let token = from_multipart(req.headers(), body.clone()).await;
+        req.set_payload(Payload::from(body));
+        Ok(token)
+    }else{
+        Ok(None)
+    }
+}
+
+/// Middleware that gives every session a random token and rejects the
+/// requests that change something without it, except those of the JSON API.
+pub async fn verify(mut req: ServiceRequest, next: Next<impl MessageBody>)
+        -> Result<ServiceResponse<impl MessageBody>, Error>{
+    let session = req.get_session();
+    let token = match session.get::<String>(SESSION_KEY).ok().flatten(){
+        Some(token) => token,
+        None => {
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            if let Err(e) = session.insert(SESSION_KEY, &token){
+                error!("Can not store the CSRF token: {}", e);
+            }
+            token
+        },
+    };
+    let safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method());
+    let exempt = EXEMPT.iter().any(|prefix| req.path().starts_with(prefix));
+    if !safe && !exempt{
+        let sent = sent_token(&mut req).await?;
+        if !sent.map(|sent| same(&sent, &token)).unwrap_or(false){
+            debug!("Invalid CSRF token for {} {}", req.method(), req.path());
+            return Err(forbidden());
+        }
+    }
+    TOKEN.scope(token, next.call(req)).await
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use actix_web::{cookie::{Cookie, Key}, http::{header, StatusCode}, App};
+    use actix_web::test::{call_service, init_service, read_body, try_call_service, TestRequest};
+    use actix_session::{SessionMiddleware, storage::CookieSessionStore};
+    use actix_web_lab::middleware::from_fn;
+
+    #[test]
+    fn same_compares_whole_strings(){
+        assert!(same("token", "token"));
+        assert!(same("", ""));
+        assert!(!same("token", "tokem"));
+        assert!(!same("token", "toke"));
+        assert!(!same("toke", "token"));
+        assert!(!same("token", ""));
+    }
+
+    /// The token of the session, and the body the route got.
+    async fn echo(body: web::Bytes) -> HttpResponse{
+        let token = TOKEN.try_with(|token| token.clone()).unwrap_or_default();
+        HttpResponse::Ok().body(format!("{}|{}", token, String::from_utf8_lossy(&body)))
+    }
+
+    macro_rules! app{
+        () => {
+            init_service(App::new()
+                .wrap(from_fn(verify))
+                .wrap(SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                    .cookie_secure(false)
+                    .build())
+                .default_service(web::to(echo))).await
+        };
+    }
+
+    /// Opens a session with a GET, returning its cookie and its token.
+    macro_rules! session{
+        ($app:expr) => {{
+            let response = call_service(&$app, TestRequest::get().uri("/").to_request()).await;
+            let cookie: Cookie<'static> = response.response().cookies().next().unwrap().into_owned();
+            let body = read_body(response).await;
+            let token = String::from_utf8_lossy(&body).trim_end_matches('|').to_string();
+            (cookie, token)
+        }};
+    }
+
+    #[actix_web::test]
+    async fn verify_lets_safe_and_exempt_requests_through(){
+        let app = app!();
+        let (cookie, token) = session!(app);
+        assert_eq!(token.len(), 32);
+        let request = TestRequest::post().uri("/api/v1/forms")
+            .cookie(cookie)
+            .to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+        let request = TestRequest::default().method(Method::HEAD).uri("/").to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn verify_rejects_missing_and_wrong_tokens(){
+        let app = app!();
+        let (cookie, token) = session!(app);
+        let request = TestRequest::post().uri("/admin/forms")
+            .cookie(cookie.clone())
+            .to_request();
+        let error = try_call_service(&app, request).await.err().unwrap();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::FORBIDDEN);
+        let request = TestRequest::post().uri("/admin/forms")
+            .cookie(cookie.clone())
+            .insert_header((HEADER, "wrong"))
+            .to_request();
+        assert!(try_call_service(&app, request).await.err().is_some());
+        // Tokens are not taken from the query string
+        let request = TestRequest::post().uri(&format!("/admin/forms?{}={}", FIELD, token))
+            .cookie(cookie.clone())
+            .to_request();
+        assert!(try_call_service(&app, request).await.err().is_some());
+        // Nor from another session
+        let request = TestRequest::post().uri("/admin/forms")
+            .insert_header((HEADER, token.as_str()))
+            .to_request();
+        assert!(try_call_service(&app, request).await.err().is_some());
+    }
+
+    #[actix_web::test]
+    async fn verify_takes_the_token_from_the_header_and_the_bodies(){
+        let app = app!();
+        let (cookie, token) = session!(app);
+        let request = TestRequest::post().uri("/admin/forms")
+            .cookie(cookie.clone())
+            .insert_header((HEADER, token.as_str()))
+            .to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+        let body = format!("name=contact&{}={}", FIELD, token);
+        let request = TestRequest::post().uri("/admin/forms")
+            .cookie(cookie.clone())
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload(body.clone())
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(read_body(response).await, format!("{}|{}", token, body));
+        let body = format!("--b\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"cv\"; filename=\"cv.txt\"\r\n\r\n\
+            content\r\n--b--\r\n", FIELD, token);
+        let request = TestRequest::post().uri("/contact")
+            .cookie(cookie.clone())
+            .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=b"))
+            .set_payload(body.clone())
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(read_body(response).await, format!("{}|{}", token, body));
+        let wrong = body.replace(&token, &"0".repeat(32));
+        let request = TestRequest::post().uri("/contact")
+            .cookie(cookie)
+            .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=b"))
+            .set_payload(wrong)
+            .to_request();
+        assert!(try_call_service(&app, request).await.err().is_some());
+    }
+}
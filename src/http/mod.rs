@@ -1 +1,5 @@
 pub mod table;
+pub mod auth;
+pub mod token;
+pub mod client;
+pub mod csrf;
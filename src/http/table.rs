@@ -1,10 +1,444 @@
-use axum::{
-    Router,
-    Json,
-    Extension,
-    extract,
-    routing::get,
-    response::IntoResponse,
+use actix_web::{
+    get,
+    post,
+    put,
+    delete,
+    web,
+    Error,
+    HttpRequest,
+    HttpResponse,
     http::StatusCode,
+    error::{JsonPayloadError, PathError, QueryPayloadError},
 };
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use std::collections::HashMap;
+use log::{debug, error};
 
+use crate::models::{Form, Field, NewField, Filter, Constraints, is_valid_field_name,
+    is_valid_form_name, validate, validate_definition, default_datatype, default_template};
+use crate::config::Configuration;
+use crate::admin::is_template;
+use crate::{notify, webhooks};
+use super::auth::{ApiUser, json_error};
+use super::client::origin;
+
+#[derive(Debug, Deserialize)]
+pub struct NewForm{
+    name: String,
+    title: String,
+    #[serde(default)]
+    instructions: String,
+    template: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatedForm{
+    title: Option<String>,
+    instructions: Option<String>,
+    template: Option<String>,
+}
+
+/// Definition of a field. Updates replace the whole definition, except the
+/// name, that can not change.
+#[derive(Debug, Deserialize)]
+pub struct FieldData{
+    #[serde(default = "default_datatype")]
+    datatype: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    placeholder: String,
+    #[serde(default)]
+    required: bool,
+    #[serde(default)]
+    unique: bool,
+    #[serde(flatten)]
+    constraints: Constraints,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page{
+    #[serde(default = "default_limit")]
+    limit: i64,
+    #[serde(default)]
+    offset: i64,
+}
+
+fn default_limit() -> i64{
+    50
+}
+
+/// Answers malformed bodies, paths and queries with the same JSON errors as
+/// the rest of the API.
+pub fn configure(cfg: &mut web::ServiceConfig){
+    cfg.app_data(web::JsonConfig::default()
+            .error_handler(|err: JsonPayloadError, _req: &HttpRequest| {
+                json_error(StatusCode::BAD_REQUEST, &err.to_string())
+            }))
+        .app_data(web::PathConfig::default()
+            .error_handler(|err: PathError, _req: &HttpRequest| {
+                json_error(StatusCode::NOT_FOUND, &err.to_string())
+            }))
+        .app_data(web::QueryConfig::default()
+            .error_handler(|err: QueryPayloadError, _req: &HttpRequest| {
+                json_error(StatusCode::BAD_REQUEST, &err.to_string())
+            }));
+}
+
+/// Unknown API paths.
+pub async fn not_found() -> Result<HttpResponse, Error>{
+    Err(json_error(StatusCode::NOT_FOUND, "Not found"))
+}
+
+fn require_admin(api_user: &ApiUser) -> Result<(), Error>{
+    if api_user.0.is_admin(){
+        Ok(())
+    }else{
+        Err(json_error(StatusCode::FORBIDDEN, "Forbidden"))
+    }
+}
+
+fn database_error(e: crate::models::Error) -> Error{
+    error!("Database error: {}", e);
+    json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+}
+
+async fn read_form(pool: &SqlitePool, name: &str) -> Result<Form, Error>{
+    Form::read_by_name(pool, name)
+        .await
+        .map_err(|_| json_error(StatusCode::NOT_FOUND, "Form not found"))
+}
+
+async fn read_fields(pool: &SqlitePool, form: &Form) -> Result<Vec<Field>, Error>{
+    form.get_fields(pool)
+        .await
+        .map_err(database_error)
+}
+
+fn find_field(fields: Vec<Field>, name: &str) -> Result<Field, Error>{
+    fields.into_iter()
+        .find(|field| field.get_name() == name)
+        .ok_or_else(|| json_error(StatusCode::NOT_FOUND, "Field not found"))
+}
+
+/// A JSON value as it would be sent by an HTML form.
+fn to_form_value(value: &Value) -> String{
+    match value{
+        Value::Null => "".to_string(),
+        Value::String(value) => value.to_string(),
+        Value::Bool(true) => "true".to_string(),
+        Value::Bool(false) => "".to_string(),
+        value => value.to_string(),
+    }
+}
+
+#[get("/forms")]
+pub async fn list_forms(api_user: ApiUser, pool: web::Data<SqlitePool>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let forms = Form::read_all(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    Ok(HttpResponse::Ok().json(forms))
+}
+
+#[post("/forms")]
+pub async fn create_form(api_user: ApiUser, template: web::Data<Tera>,
+        pool: web::Data<SqlitePool>, data: web::Json<NewForm>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let data = data.into_inner();
+    if !is_valid_form_name(&data.name){
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid name"));
+    }
+    if Form::read_by_name(pool.get_ref(), &data.name).await.is_ok(){
+        return Err(json_error(StatusCode::CONFLICT, "Form already exists"));
+    }
+    let deleted = Form::read_deleted(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    if deleted.iter().any(|form| form.name == data.name){
+        return Err(json_error(StatusCode::CONFLICT, "Form is in the trash"));
+    }
+    let form_template = data.template.unwrap_or_else(default_template);
+    if !is_template(&template, &form_template){
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, "Unknown template"));
+    }
+    let form = Form::new(pool.get_ref(), data.name, data.title, data.instructions,
+            form_template)
+        .await
+        .map_err(database_error)?;
+    Ok(HttpResponse::Created().json(form))
+}
+
+#[get("/forms/{form}")]
+pub async fn get_form(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<String>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let form = read_form(pool.get_ref(), &path).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "form": form,
+        "fields": fields,
+    })))
+}
+
+#[put("/forms/{form}")]
+pub async fn update_form(api_user: ApiUser, template: web::Data<Tera>,
+        pool: web::Data<SqlitePool>, path: web::Path<String>, data: web::Json<UpdatedForm>)
+        -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let mut form = read_form(pool.get_ref(), &path).await?;
+    let data = data.into_inner();
+    if let Some(title) = data.title{
+        form.title = title;
+    }
+    if let Some(instructions) = data.instructions{
+        form.instructions = instructions;
+    }
+    if let Some(form_template) = data.template{
+        if !is_template(&template, &form_template){
+            return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, "Unknown template"));
+        }
+        form.template = form_template;
+    }
+    let form = form.save(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    Ok(HttpResponse::Ok().json(form))
+}
+
+#[delete("/forms/{form}")]
+pub async fn delete_form(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<String>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let form = read_form(pool.get_ref(), &path).await?;
+    Form::remove(pool.get_ref(), form.id)
+        .await
+        .map_err(database_error)?;
+    debug!("Form {} moved to the trash", form.name);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/forms/{form}/fields")]
+pub async fn list_fields(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<String>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let form = read_form(pool.get_ref(), &path).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    Ok(HttpResponse::Ok().json(fields))
+}
+
+#[post("/forms/{form}/fields")]
+pub async fn create_field(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<String>, data: web::Json<NewField>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let form = read_form(pool.get_ref(), &path).await?;
+    let data = data.into_inner();
+    if !is_valid_field_name(&data.name){
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid name"));
+    }
+    if let Err(message) = validate_definition(&data.datatype, &data.constraints){
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, &message));
+    }
+    // fields in the trash still hold their names
+    let fields = Field::read_all_by_form_id(pool.get_ref(), form.id)
+        .await
+        .map_err(database_error)?;
+    match fields.iter().find(|field| field.get_name() == data.name){
+        Some(field) if field.get_deleted_at().is_some() =>
+            return Err(json_error(StatusCode::CONFLICT, "Field is in the trash")),
+        Some(_) => return Err(json_error(StatusCode::CONFLICT, "Field already exists")),
+        None => {},
+    }
+    let field = Field::new(pool.get_ref(), form.id, data)
+        .await
+        .map_err(|e| {
+            error!("Can not create field: {}", e);
+            json_error(StatusCode::UNPROCESSABLE_ENTITY, "Can not create the field")
+        })?;
+    Ok(HttpResponse::Created().json(field))
+}
+
+#[put("/forms/{form}/fields/{field}")]
+pub async fn update_field(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, String)>, data: web::Json<FieldData>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let (form_name, field_name) = path.into_inner();
+    let form = read_form(pool.get_ref(), &form_name).await?;
+    let mut field = find_field(read_fields(pool.get_ref(), &form).await?, &field_name)?;
+    let data = data.into_inner();
+    if let Err(message) = validate_definition(&data.datatype, &data.constraints){
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, &message));
+    }
+    field.set_datatype(data.datatype);
+    field.set_label(data.label);
+    field.set_placeholder(data.placeholder);
+    field.set_required(data.required);
+    field.set_unique(data.unique);
+    field.set_constraints(data.constraints);
+    let field = field.save(pool.get_ref())
+        .await
+        .map_err(|e| {
+            error!("Can not save field: {}", e);
+            json_error(StatusCode::UNPROCESSABLE_ENTITY, "Can not save the field")
+        })?;
+    Ok(HttpResponse::Ok().json(field))
+}
+
+#[delete("/forms/{form}/fields/{field}")]
+pub async fn delete_field(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, String)>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let (form_name, field_name) = path.into_inner();
+    let form = read_form(pool.get_ref(), &form_name).await?;
+    let field = find_field(read_fields(pool.get_ref(), &form).await?, &field_name)?;
+    Field::remove(pool.get_ref(), field.get_id())
+        .await
+        .map_err(database_error)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Values of a submission sent as JSON, as if they came from a form. Files
+/// can only be sent through the forms, so values of `file` fields are dropped
+/// and can not point to the upload of another submission.
+fn to_form_values(fields: &[Field], data: &HashMap<String, Value>) -> HashMap<String, String>{
+    data.iter()
+        .filter(|(key, _)| !fields.iter()
+            .any(|field| field.get_datatype() == "file" && field.get_name() == key.as_str()))
+        .map(|(key, value)| (key.to_string(), to_form_value(value)))
+        .collect()
+}
+
+#[post("/forms/{form}/submissions")]
+pub async fn create_submission(req: HttpRequest, _api_user: ApiUser,
+        pool: web::Data<SqlitePool>, conf: web::Data<Configuration>, path: web::Path<String>,
+        data: web::Json<HashMap<String, Value>>) -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), &path).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    let values = to_form_values(&fields, &data);
+    let errors = validate(&fields, &values);
+    if !errors.is_empty(){
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Validation failed",
+            "fields": errors,
+        })));
+    }
+    match form.insert(pool.get_ref(), &fields, &values, &origin(&req, &conf)).await{
+        Ok(id) => {
+            webhooks::submitted(pool.get_ref(), &form, &fields, id).await;
+            notify::submitted(pool.get_ref().clone(), form.clone(), fields.clone(),
+                id, values.clone());
+            let row = form.read_row(pool.get_ref(), id)
+                .await
+                .map_err(database_error)?;
+            Ok(HttpResponse::Created().json(Form::row_to_json(&fields, &row)))
+        },
+        Err(e) => match form.duplicated(&fields, &e){
+            Some(duplicated) => Ok(HttpResponse::Conflict().json(json!({
+                "error": "Duplicated value",
+                "fields": HashMap::from([duplicated]),
+            }))),
+            None => Err(database_error(e)),
+        },
+    }
+}
+
+#[get("/forms/{form}/submissions")]
+pub async fn list_submissions(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<String>, page: web::Query<Page>,
+        params: web::Query<HashMap<String, String>>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let form = read_form(pool.get_ref(), &path).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    let filter = Filter::from_query(&fields, &params);
+    let total = form.count(pool.get_ref(), &fields, &filter)
+        .await
+        .map_err(database_error)?;
+    let limit = page.limit.clamp(1, 1000);
+    let offset = page.offset.max(0);
+    let rows = form.read_rows(pool.get_ref(), &fields, &filter, Some(limit), offset)
+        .await
+        .map_err(database_error)?;
+    let data: Vec<_> = rows.iter()
+        .map(|row| Form::row_to_json(&fields, row))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+        "data": data,
+    })))
+}
+
+#[get("/forms/{form}/submissions/{id}")]
+pub async fn get_submission(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, i64)>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let (form_name, id) = path.into_inner();
+    let form = read_form(pool.get_ref(), &form_name).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    let row = form.read_row(pool.get_ref(), id)
+        .await
+        .map_err(|_| json_error(StatusCode::NOT_FOUND, "Submission not found"))?;
+    Ok(HttpResponse::Ok().json(Form::row_to_json(&fields, &row)))
+}
+
+#[put("/forms/{form}/submissions/{id}")]
+pub async fn update_submission(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, i64)>, data: web::Json<HashMap<String, Value>>)
+        -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let (form_name, id) = path.into_inner();
+    let form = read_form(pool.get_ref(), &form_name).await?;
+    let fields = read_fields(pool.get_ref(), &form).await?;
+    let mut values = to_form_values(&fields, &data);
+    // The files of the submission are kept
+    if let Ok(row) = form.read_row(pool.get_ref(), id).await{
+        let current = Form::row_to_json(&fields, &row);
+        for field in fields.iter().filter(|field| field.get_datatype() == "file"){
+            if let Some(value) = current.get(field.get_name()){
+                values.insert(field.get_name().to_string(), to_form_value(value));
+            }
+        }
+    }
+    let errors = validate(&fields, &values);
+    if !errors.is_empty(){
+        return Ok(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "Validation failed",
+            "fields": errors,
+        })));
+    }
+    match form.update_row(pool.get_ref(), &fields, id, &values).await{
+        Ok(_) => {
+            let row = form.read_row(pool.get_ref(), id)
+                .await
+                .map_err(database_error)?;
+            Ok(HttpResponse::Ok().json(Form::row_to_json(&fields, &row)))
+        },
+        Err(e) => match e.downcast_ref::<sqlx::Error>(){
+            Some(sqlx::Error::RowNotFound) =>
+                Err(json_error(StatusCode::NOT_FOUND, "Submission not found")),
+            _ => match form.duplicated(&fields, &e){
+                Some(duplicated) => Ok(HttpResponse::Conflict().json(json!({
+                    "error": "Duplicated value",
+                    "fields": HashMap::from([duplicated]),
+                }))),
+                None => Err(database_error(e)),
+            },
+        },
+    }
+}
+
+#[delete("/forms/{form}/submissions/{id}")]
+pub async fn delete_submission(api_user: ApiUser, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, i64)>) -> Result<HttpResponse, Error>{
+    require_admin(&api_user)?;
+    let (form_name, id) = path.into_inner();
+    let form = read_form(pool.get_ref(), &form_name).await?;
+    form.delete_row(pool.get_ref(), id)
+        .await
+        .map_err(|_| json_error(StatusCode::NOT_FOUND, "Submission not found"))?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,43 @@
+use actix_web::{get, post, delete, web, Error, HttpResponse, http::StatusCode};
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use log::debug;
+
+use crate::models::{User, UserSchema, FilteredUser};
+use super::auth::{ApiUser, issue_token, revoke_token, json_error};
+
+/// Exchanges the credentials of an active user for a bearer token.
+#[post("/token")]
+pub async fn post_token(pool: web::Data<SqlitePool>, credentials: web::Json<UserSchema>)
+        -> Result<HttpResponse, Error>{
+    let user = User::login(pool.get_ref(), &credentials.name, &credentials.password)
+        .await
+        .map_err(|_| json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error"))?
+        .ok_or_else(|| json_error(StatusCode::UNAUTHORIZED, "Wrong name or password"))?;
+    let (token, expires_in) = issue_token(pool.get_ref(), &user)
+        .await
+        .map_err(|_| json_error(StatusCode::INTERNAL_SERVER_ERROR, "Can not issue token"))?;
+    debug!("Token issued for {}", user.name);
+    Ok(HttpResponse::Ok().json(json!({
+        "access_token": token,
+        "token_type": "Bearer",
+        "expires_in": expires_in,
+    })))
+}
+
+/// Revokes the bearer token of the request.
+#[delete("/token")]
+pub async fn delete_token(pool: web::Data<SqlitePool>, api_user: ApiUser)
+        -> Result<HttpResponse, Error>{
+    revoke_token(pool.get_ref(), &api_user.1)
+        .await
+        .map_err(|_| json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error"))?;
+    debug!("Token revoked for {}", api_user.0.name);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The user of the bearer token.
+#[get("/me")]
+pub async fn get_me(api_user: ApiUser) -> Result<HttpResponse, Error>{
+    Ok(HttpResponse::Ok().json(FilteredUser::from(&api_user.0)))
+}
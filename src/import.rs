@@ -0,0 +1,311 @@
+use actix_web::{get, post, web, Result, error, Error, Responder};
+use actix_web_lab::respond::Html;
+use actix_multipart::form::{MultipartForm, MultipartFormConfig, tempfile::TempFile, text::Text};
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use serde::Serialize;
+use std::collections::HashMap;
+use log::{debug, error};
+
+use crate::models::{Field, Form, Origin, validate};
+use crate::admin::{read_form, render};
+use crate::http::{auth::Admin, csrf};
+
+/// Largest CSV file that can be imported. Its content is sent back from the
+/// mapping page as text, so it is also the limit of what is read in memory,
+/// which is why it is below `uploads::MAX_MULTIPART_SIZE`, the limit of any
+/// multipart form, whose files go to disk.
+const MAX_IMPORT_SIZE: usize = 20 * 1024 * 1024;
+
+/// An upload of a CSV file, or its content sent back from the mapping page
+/// with the column chosen for every field.
+#[derive(Debug, MultipartForm)]
+pub struct ImportSchema{
+    file: Option<TempFile>,
+    content: Option<Text<String>>,
+    /// Index of the column for every field, in the order of the fields.
+    /// Empty to leave the field empty.
+    mapping: Vec<Text<String>>,
+    dry_run: Option<Text<String>>,
+}
+
+/// What happened to a row of the CSV file.
+#[derive(Debug, Serialize)]
+struct RowReport{
+    line: usize,
+    errors: Vec<String>,
+}
+
+/// Everything known about a CSV file once its rows have been imported, or
+/// only checked.
+struct Import{
+    headers: Vec<String>,
+    mapping: Vec<Option<usize>>,
+    reports: Vec<RowReport>,
+    failed: usize,
+    imported: bool,
+}
+
+/// Lets the whole CSV file, and its content sent back as text, through the
+/// multipart form of the import.
+pub fn configure(cfg: &mut web::ServiceConfig){
+    cfg.app_data(MultipartFormConfig::default()
+        .total_limit(MAX_IMPORT_SIZE)
+        .memory_limit(MAX_IMPORT_SIZE));
+}
+
+fn normalize(value: &str) -> String{
+    value.trim().to_lowercase()
+}
+
+/// Column of every field, matching the headers of the file with the names
+/// or the labels of the fields.
+fn auto_mapping(fields: &[Field], headers: &[String]) -> Vec<Option<usize>>{
+    fields.iter()
+        .map(|field| {
+            let name = normalize(field.get_name());
+            let label = normalize(field.get_label());
+            headers.iter()
+                .position(|header| normalize(header) == name)
+                .or_else(|| headers.iter()
+                    .position(|header| !label.is_empty() && normalize(header) == label))
+        })
+        .collect()
+}
+
+fn read_content(data: &ImportSchema) -> Result<String, Error>{
+    let bytes = match &data.file{
+        Some(file) if file.size > 0 => std::fs::read(file.file.path())
+            .map_err(|_| error::ErrorBadRequest("Can not read the file"))?,
+        _ => data.content.as_ref()
+            .map(|content| content.as_bytes().to_vec())
+            .unwrap_or_default(),
+    };
+    let content = String::from_utf8(bytes)
+        .map_err(|_| error::ErrorBadRequest("The file must be UTF-8"))?;
+    Ok(content.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Imports the rows of a CSV file as submissions of a form. Every row goes
+/// through the same validation as the public form, and all of them are
+/// inserted in a single transaction that is only committed when none has
+/// errors and this is not a dry run. `chosen` has the column of every field,
+/// or is empty to match them by their headers.
+async fn import(pool: &SqlitePool, form: &Form, fields: &[Field], content: &str,
+        chosen: &[String], dry_run: bool) -> Result<Import, Error>{
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let headers: Vec<String> = reader.headers()
+        .map_err(|_| error::ErrorBadRequest("The file is not a valid CSV"))?
+        .iter()
+        .map(|header| header.to_string())
+        .collect();
+    let mapping = if chosen.len() == fields.len(){
+        chosen.iter()
+            .map(|index| index.parse::<usize>().ok().filter(|index| *index < headers.len()))
+            .collect()
+    }else{
+        auto_mapping(fields, &headers)
+    };
+    let mut reports = Vec::new();
+    let mut tx = pool.begin()
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    for (index, record) in reader.records().enumerate(){
+        let line = index + 2;
+        let record = match record{
+            Ok(record) => record,
+            Err(e) => {
+                reports.push(RowReport{line, errors: vec![e.to_string()]});
+                continue;
+            },
+        };
+        // Files can only be sent through the forms
+        let values: HashMap<String, String> = fields.iter()
+            .zip(mapping.iter())
+            .filter(|(field, _)| field.get_datatype() != "file")
+            .filter_map(|(field, column)| column
+                .and_then(|column| record.get(column))
+                .map(|value| (field.get_name().to_string(), value.trim().to_string())))
+            .collect();
+        let mut invalid = validate(fields, &values);
+        let mut errors: Vec<String> = fields.iter()
+            .filter_map(|field| invalid.remove(field.get_name())
+                .map(|message| format!("{}: {}", field.get_label(), message)))
+            .collect();
+        if errors.is_empty(){
+            if let Err(e) = form.insert(&mut tx, fields, &values, &Origin::default()).await{
+                match form.duplicated(fields, &e){
+                    Some((_, message)) => errors.push(message),
+                    None => {
+                        error!("Can not import line {} into {}: {}", line, form.name, e);
+                        errors.push("Database error".to_string());
+                    },
+                }
+            }
+        }
+        reports.push(RowReport{line, errors});
+    }
+    let failed = reports.iter().filter(|report| !report.errors.is_empty()).count();
+    let imported = !dry_run && failed == 0 && !reports.is_empty();
+    if imported{
+        tx.commit()
+            .await
+            .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+        debug!("Imported {} rows into {}", reports.len(), form.name);
+    }else{
+        tx.rollback()
+            .await
+            .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    }
+    Ok(Import{headers, mapping, reports, failed, imported})
+}
+
+#[get("/forms/{id}/import")]
+pub async fn get_import(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>) -> Result<impl Responder, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("form", &form);
+    ctx.insert("dry_run", &true);
+    Ok(Html(render(&template, "admin/import.html", &ctx)?))
+}
+
+/// Imports a CSV file into a form and shows how every row went, with the
+/// columns chosen for the fields to try again.
+#[post("/forms/{id}/import")]
+pub async fn post_import(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>, data: MultipartForm<ImportSchema>) -> Result<impl Responder, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let fields = form.get_fields(pool.get_ref())
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let content = read_content(&data)?;
+    let dry_run = data.dry_run.is_some();
+    let chosen: Vec<String> = data.mapping.iter()
+        .map(|index| index.to_string())
+        .collect();
+    let Import{headers, mapping, reports, failed, imported} =
+        import(pool.get_ref(), &form, &fields, &content, &chosen, dry_run).await?;
+    let mapping: Vec<String> = mapping.iter()
+        .map(|column| column.map(|column| column.to_string()).unwrap_or_default())
+        .collect();
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("form", &form);
+    ctx.insert("fields", &fields);
+    ctx.insert("headers", &headers);
+    ctx.insert("mapping", &mapping);
+    ctx.insert("content", &content);
+    ctx.insert("dry_run", &dry_run);
+    ctx.insert("reports", &reports);
+    ctx.insert("total", &reports.len());
+    ctx.insert("failed", &failed);
+    ctx.insert("imported", &imported);
+    Ok(Html(render(&template, "admin/import.html", &ctx)?))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::migrations::{labelled_field, test_field, test_form};
+    use crate::models::{Filter, NewField};
+
+    fn headers(headers: &[&str]) -> Vec<String>{
+        headers.iter().map(|header| header.to_string()).collect()
+    }
+
+    #[test]
+    fn auto_mapping_matches_names_and_labels(){
+        let fields = [labelled_field("email", "Email address"),
+            labelled_field("name", "Full name")];
+        assert_eq!(auto_mapping(&fields, &headers(&["name", "email"])),
+            vec![Some(1), Some(0)]);
+        assert_eq!(auto_mapping(&fields, &headers(&["Full name", "Email address"])),
+            vec![Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn auto_mapping_ignores_case_and_whitespace(){
+        let fields = [labelled_field("email", "Email address"),
+            labelled_field("name", "Full name")];
+        assert_eq!(auto_mapping(&fields, &headers(&[" EMAIL ", "full NAME\t"])),
+            vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn auto_mapping_leaves_fields_without_column(){
+        let fields = [labelled_field("email", "Email address"),
+            labelled_field("phone", "")];
+        assert_eq!(auto_mapping(&fields, &headers(&["email", ""])), vec![Some(0), None]);
+        assert_eq!(auto_mapping(&fields, &[]), vec![None, None]);
+    }
+
+    /// A form with a required and unique `email` and an optional `name`.
+    async fn form(pool: &SqlitePool) -> (Form, Vec<Field>){
+        test_form(pool, "contact", vec![
+            NewField{required: true, unique: true, ..test_field("email", "email")},
+            test_field("name", "text"),
+        ]).await
+    }
+
+    async fn count(pool: &SqlitePool, form: &Form, fields: &[Field]) -> i64{
+        form.count(pool, fields, &Filter::default()).await.unwrap()
+    }
+
+    const CLEAN: &str = "email,name\none@example.com,One\ntwo@example.com,Two\n";
+
+    #[actix_web::test]
+    async fn import_commits_a_clean_file(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, fields) = form(&pool).await;
+        let report = import(&pool, &form, &fields, CLEAN, &[], false).await.unwrap();
+        assert!(report.imported);
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.reports.len(), 2);
+        assert_eq!(count(&pool, &form, &fields).await, 2);
+    }
+
+    #[actix_web::test]
+    async fn import_rolls_back_a_dry_run(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, fields) = form(&pool).await;
+        let report = import(&pool, &form, &fields, CLEAN, &[], true).await.unwrap();
+        assert!(!report.imported);
+        assert_eq!(report.failed, 0);
+        assert_eq!(count(&pool, &form, &fields).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn import_rolls_back_when_a_row_fails(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, fields) = form(&pool).await;
+        let invalid = "email,name\none@example.com,One\nnot an email,Two\n";
+        let report = import(&pool, &form, &fields, invalid, &[], false).await.unwrap();
+        assert!(!report.imported);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.reports[1].line, 3);
+        assert_eq!(count(&pool, &form, &fields).await, 0);
+        // A duplicated value fails in the database, after the first row
+        let duplicated = "email,name\none@example.com,One\none@example.com,Two\n";
+        let report = import(&pool, &form, &fields, duplicated, &[], false).await.unwrap();
+        assert!(!report.imported);
+        assert_eq!(report.reports[1].errors,
+            vec!["This email has already been registered".to_string()]);
+        assert_eq!(count(&pool, &form, &fields).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn import_follows_the_chosen_columns(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, fields) = form(&pool).await;
+        let content = "Name,Address\nOne,one@example.com\n";
+        let chosen = ["1".to_string(), "".to_string()];
+        let report = import(&pool, &form, &fields, content, &chosen, false).await.unwrap();
+        assert_eq!(report.mapping, vec![Some(1), None]);
+        assert!(report.imported);
+        assert_eq!(count(&pool, &form, &fields).await, 1);
+    }
+}
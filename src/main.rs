@@ -1,4 +1,15 @@
+mod admin;
+mod audit;
+mod config;
+mod export;
+mod import;
+mod migrations;
+mod notify;
 mod routes;
+mod spam;
+mod trash;
+mod uploads;
+mod webhooks;
 mod models;
 mod http;
 
@@ -6,23 +17,31 @@ use actix_web::{
     HttpServer,
     App,
     web::{self, Data},
-    middleware::Logger
+    middleware::Logger,
+    cookie::Key,
 };
+use actix_session::{SessionMiddleware, storage::CookieSessionStore};
+use actix_web_lab::middleware::from_fn;
 
 use std::process;
 use tokio::fs;
-use sqlx::{query, sqlite::{SqlitePool, SqlitePoolOptions},
+use sqlx::{sqlite::{SqlitePool, SqlitePoolOptions},
     migrate::MigrateDatabase};
 use env_logger::Env;
-use log::{debug, error};
+use log::{debug, error, warn};
 use tera::Tera;
-use actix_web_httpauth::extractors::basic;
-use actix_files;
 
 use crate::config::Configuration;
+use crate::models::{Challenge, Form, Field, NewField, User, Role, Param, Token, Trash,
+    is_valid_field_name, is_valid_form_name, validate_definition};
+use crate::http::{auth, csrf, token, table};
 
 
 
+/// Seconds between purges of the trash, revoked tokens, spent challenges
+/// and unused uploads while the server runs.
+const TRASH_PURGE_INTERVAL: u64 = 3600;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let content = match fs::read_to_string("config.yml")
@@ -30,7 +49,7 @@ async fn main() -> std::io::Result<()> {
             Ok(value) => value,
             Err(e) => {
                 println!("Error with config file `config.yml`: {}",
-                    e.to_string());
+                    e);
                 process::exit(0);
             }
         };
@@ -52,33 +71,149 @@ async fn main() -> std::io::Result<()> {
             process::exit(1);
         }
     };
-    if !sqlx::Sqlite::database_exists(&db_url).await.unwrap(){
-        sqlx::Sqlite::create_database(&db_url).await.unwrap();
+    if !sqlx::Sqlite::database_exists(db_url).await.unwrap(){
+        sqlx::Sqlite::create_database(db_url).await.unwrap();
     }
 
     let pool = SqlitePoolOptions::new()
         .max_connections(4)
-        .connect(&db_url)
+        .connect(db_url)
         .await
         .expect("Pool failed");
 
-    
+    let migrate_only = std::env::args().nth(1).as_deref() == Some("migrate");
+    match migrations::run(&pool).await{
+        Ok(version) => {
+            debug!("Schema version: {}", version);
+            if migrate_only{
+                println!("Database migrated to version {}", version);
+                process::exit(0);
+            }
+        },
+        Err(e) => {
+            error!("Can not migrate database, {}", e);
+            process::exit(1);
+        }
+    }
+
     init(&pool, &configuration).await;
 
+    let purge_pool = pool.clone();
+    let purge_conf = configuration.clone();
+    actix_web::rt::spawn(async move {
+        loop{
+            actix_web::rt::time::sleep(std::time::Duration::from_secs(TRASH_PURGE_INTERVAL)).await;
+            match Trash::purge_expired(&purge_pool).await{
+                Ok(purged) => debug!("Purged {} items from the trash", purged),
+                Err(e) => error!("Can not purge the trash, {}", e),
+            }
+            match Token::purge_expired(&purge_pool).await{
+                Ok(purged) => debug!("Purged {} expired revoked tokens", purged),
+                Err(e) => error!("Can not purge revoked tokens, {}", e),
+            }
+            match Challenge::purge_expired(&purge_pool).await{
+                Ok(purged) => debug!("Purged {} expired spent challenges", purged),
+                Err(e) => error!("Can not purge spent challenges, {}", e),
+            }
+            uploads::sweep(purge_pool.clone(), purge_conf.clone()).await;
+        }
+    });
+
+    actix_web::rt::spawn(webhooks::run(pool.clone()));
+
+    let session_key = Param::get_session_key(&pool).await;
+    let key = Key::from(session_key.as_bytes());
+    let secure_cookies = configuration.secure_cookies;
+
     let conf = configuration.clone();
+    let limiter = Data::new(spam::RateLimiter::default());
     HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(auth::audit_actor))
+            .wrap(from_fn(csrf::verify))
             .wrap(Logger::default())
-.app_data(Data::new(pool.clone()))
+            .wrap(SessionMiddleware::builder(CookieSessionStore::default(), key.clone())
+                .cookie_secure(secure_cookies)
+                .build())
+            .app_data(Data::new(pool.clone()))
             .app_data(Data::new(conf.clone()))
             .app_data(Data::new(template.clone()))
-            .service(routes::get_form)
-            .service(routes::post_form)
+            .app_data(limiter.clone())
+            .service(actix_files::Files::new("/static", "./static"))
+            .service(routes::get_login)
+            .service(routes::post_login)
+            .service(routes::post_logout)
+            .service(
+                web::scope("api")
+                .service(token::post_token)
+                .service(
+                    web::scope("v1")
+                    .wrap(from_fn(auth::bearer))
+                    .configure(table::configure)
+                    .service(token::delete_token)
+                    .service(token::get_me)
+                    .service(table::list_forms)
+                    .service(table::create_form)
+                    .service(table::get_form)
+                    .service(table::update_form)
+                    .service(table::delete_form)
+                    .service(table::list_fields)
+                    .service(table::create_field)
+                    .service(table::update_field)
+                    .service(table::delete_field)
+                    .service(table::create_submission)
+                    .service(table::list_submissions)
+                    .service(table::get_submission)
+                    .service(table::update_submission)
+                    .service(table::delete_submission)
+                    .default_service(web::to(table::not_found))))
+            .service(
+                web::scope("admin")
+                .configure(import::configure)
+                .service(admin::get_forms)
+                .service(admin::post_forms)
+                .service(admin::get_preview)
+                .service(admin::get_form)
+                .service(admin::post_form)
+                .service(admin::delete_form)
+                .service(admin::post_notification)
+                .service(admin::post_protection)
+                .service(admin::post_fields)
+                .service(admin::post_field)
+                .service(admin::move_field_up)
+                .service(admin::move_field_down)
+                .service(admin::delete_field)
+                .service(import::get_import)
+                .service(import::post_import)
+                .service(trash::get_trash)
+                .service(trash::post_trash_submission)
+                .service(trash::post_trash)
+                .service(audit::get_audit_csv)
+                .service(audit::get_audit)
+                .service(notify::get_smtp)
+                .service(notify::post_smtp)
+                .service(notify::post_smtp_test)
+                .service(webhooks::post_webhooks)
+                .service(webhooks::get_webhook)
+                .service(webhooks::post_webhook)
+                .service(webhooks::delete_webhook)
+                .service(webhooks::post_redeliver))
             .service(
                 web::scope("results")
-                .app_data(basic::Config::default().realm("Restricted area"))
+                .service(export::get_csv)
+                .service(export::get_jsonl)
+                .service(export::get_xlsx)
+                .service(uploads::get_file)
+                .service(routes::get_submission)
+                .service(routes::post_submission)
+                .service(routes::get_delete_submission)
+                .service(routes::post_delete_submission)
                 .service(routes::get_results))
-            .service(actix_files::Files::new("/static", "./static"))
+            .service(
+                web::scope("")
+                .wrap(from_fn(spam::rate_limit))
+                .service(routes::get_form)
+                .service(routes::post_form))
     })
     .workers(4)
     .bind(format!("0.0.0.0:{}", &port))
@@ -87,18 +222,90 @@ async fn main() -> std::io::Result<()> {
     .await
 }
 
+/// Creates the administrator of `config.yml` if there is no active
+/// administrator, unless it is in the trash, and imports the tables of `config.yml` as
+/// forms. Forms that already exist in the database, even in the trash, are
+/// left untouched, so they can be changed at runtime. Every field saved
+/// brings the table of its form up to date, and the tables of all the forms
+/// are checked against their fields at startup.
 async fn init(pool: &SqlitePool, config: &Configuration){
-    for table_name in config.tables.keys(){
-        match config.get_table(table_name){
-            Some(table) => {
-                let sql = table.create(table_name);
-                debug!("Sql creation query: {}", &sql);
-                query(&sql)
-                    .execute(pool)
-                    .await
-                    .unwrap();
-            },
-            None => {}
+    match Token::purge_expired(pool).await{
+        Ok(purged) => debug!("Purged {} expired revoked tokens", purged),
+        Err(e) => error!("Can not purge revoked tokens, {}", e),
+    }
+    match Challenge::purge_expired(pool).await{
+        Ok(purged) => debug!("Purged {} expired spent challenges", purged),
+        Err(e) => error!("Can not purge spent challenges, {}", e),
+    }
+    match Trash::purge_expired(pool).await{
+        Ok(purged) => debug!("Purged {} items from the trash", purged),
+        Err(e) => error!("Can not purge the trash, {}", e),
+    }
+    match User::has_active_admin(pool).await{
+        Ok(true) => debug!("There is an active administrator"),
+        Ok(false) => {
+            let in_trash = User::read_deleted(pool).await.unwrap_or_default()
+                .iter()
+                .any(|user| user.name == config.username);
+            if in_trash{
+                warn!("Administrator {} is in the trash, restore it to sign in",
+                    config.username);
+            }else{
+                debug!("Creating administrator {}", config.username);
+                if let Err(e) = User::new(pool, config.username.clone(),
+                        config.password.clone(), Role::Admin, true).await{
+                    error!("Can not create administrator {}, {}", config.username, e);
+                }
+            }
+        },
+        Err(e) => error!("Can not look for an administrator, {}", e),
+    }
+    let deleted_forms = Form::read_deleted(pool).await.unwrap_or_default();
+    for (table_name, table) in config.tables.iter(){
+        if !is_valid_form_name(table_name){
+            error!("Form {} has a reserved or invalid name", table_name);
+            continue;
+        }
+        if Form::read_by_name(pool, table_name).await.is_ok(){
+            debug!("Form {} already exists", table_name);
+            continue;
+        }
+        if deleted_forms.iter().any(|form| &form.name == table_name){
+            debug!("Form {} is in the trash", table_name);
+            continue;
+        }
+        let form = Form::new(pool, table_name.to_string(), table.title.clone(),
+            table.instructions.clone(), table.template.clone())
+            .await
+            .unwrap();
+        for field in table.fields.as_slice(){
+            if !is_valid_field_name(&field.name){
+                error!("Field {} of {} has a reserved or invalid name", field.name, table_name);
+                continue;
+            }
+            if let Err(message) = validate_definition(&field.datatype, &field.constraints){
+                error!("Field {} of {} is not valid: {}", field.name, table_name, message);
+                continue;
+            }
+            let field = NewField{
+                name: field.name.clone(),
+                datatype: field.datatype.clone(),
+                label: field.label.clone(),
+                placeholder: field.placeholder.clone(),
+                required: field.required,
+                unique: field.unique,
+                constraints: field.constraints.clone(),
+            };
+            Field::new(pool, form.id, field)
+                .await
+                .unwrap();
+        }
+    }
+    for form in Form::read_all(pool).await.unwrap(){
+        let mut tx = pool.begin().await.unwrap();
+        match form.sync_table(&mut tx).await{
+            Ok(()) => tx.commit().await.unwrap(),
+            Err(e) => error!("Can not sync table of form {}, {}", form.name, e),
         }
     }
 }
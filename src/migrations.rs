@@ -0,0 +1,117 @@
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    migrate::Migrator,
+    query,
+    Row
+};
+use tracing::{info, debug};
+
+use crate::models::Error;
+
+/// Migrations of the internal schema, embedded in the binary and applied in
+/// order. Every applied version is recorded in `_sqlx_migrations`.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Latest version known by this binary.
+pub fn latest_version() -> i64{
+    MIGRATOR.iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Latest version applied to the database, or 0 if it has never been
+/// migrated.
+pub async fn current_version(pool: &SqlitePool) -> Result<i64, Error>{
+    debug!("current_version");
+    let sql = "SELECT count(name) FROM sqlite_master WHERE type = 'table'
+               AND name = '_sqlx_migrations'";
+    let exists = query(sql)
+        .map(|row: SqliteRow| -> i64 {row.get(0)})
+        .fetch_one(pool)
+        .await? > 0;
+    if !exists{
+        return Ok(0);
+    }
+    let sql = "SELECT COALESCE(MAX(version), 0) FROM _sqlx_migrations
+               WHERE success = TRUE";
+    query(sql)
+        .map(|row: SqliteRow| -> i64 {row.get(0)})
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Applies the pending migrations. Refuses to touch a database whose schema
+/// is newer than this binary.
+pub async fn run(pool: &SqlitePool) -> Result<i64, Error>{
+    info!("run");
+    let current = current_version(pool).await?;
+    let latest = latest_version();
+    if current > latest{
+        return Err(format!("Database schema version {} is newer than the \
+            latest known by this binary ({}). Upgrade asker.", current,
+            latest).into());
+    }
+    MIGRATOR.run(pool).await?;
+    let applied = current_version(pool).await?;
+    info!("Schema version: {}", applied);
+    Ok(applied)
+}
+
+/// A migrated database in memory, for the tests. It has a single
+/// connection, as every connection to `sqlite::memory:` opens a database of
+/// its own, so code that waits for a second one fails soon instead of
+/// hanging.
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool{
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(std::time::Duration::from_secs(5))
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    run(&pool).await.unwrap();
+    pool
+}
+
+/// A new optional field labelled as it is named, for the tests.
+#[cfg(test)]
+pub fn test_field(name: &str, datatype: &str) -> crate::models::NewField{
+    crate::models::NewField{
+        name: name.to_string(),
+        datatype: datatype.to_string(),
+        label: name.to_string(),
+        placeholder: String::new(),
+        required: false,
+        unique: false,
+        constraints: Default::default(),
+    }
+}
+
+/// A text field that is not stored, with a label, for the tests.
+#[cfg(test)]
+pub fn labelled_field(name: &str, label: &str) -> crate::models::Field{
+    let mut field = crate::models::Field::sample(name, "text", false, Default::default());
+    field.set_label(label.to_string());
+    field
+}
+
+/// A stored form with these fields, and its fields as stored, for the tests.
+/// Its title is its name capitalized.
+#[cfg(test)]
+pub async fn test_form(pool: &SqlitePool, name: &str, fields: Vec<crate::models::NewField>)
+        -> (crate::models::Form, Vec<crate::models::Field>){
+    use crate::models::{Field, Form};
+    let title = name[..1].to_uppercase() + &name[1..];
+    let form = Form::new(pool, name.to_string(), title, String::new(),
+        "index.html".to_string()).await.unwrap();
+    for field in fields{
+        Field::new(pool, form.id, field).await.unwrap();
+    }
+    let fields = form.get_fields(pool).await.unwrap();
+    (form, fields)
+}
@@ -0,0 +1,190 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::{info, debug, error};
+use std::{collections::HashMap, future::Future};
+
+// my own uses
+use super::Error;
+
+tokio::task_local!{
+    /// Name of who is making the changes of the current request.
+    static ACTOR: String;
+}
+
+/// Actor of the changes made outside of any request, like at startup.
+const SYSTEM: &str = "system";
+
+/// Runs `future` with `actor` as the author of every change recorded in it.
+pub async fn as_actor<F: Future>(actor: String, future: F) -> F::Output{
+    ACTOR.scope(actor, future).await
+}
+
+fn current_actor() -> String{
+    ACTOR.try_with(|actor| actor.clone())
+        .unwrap_or_else(|_| SYSTEM.to_string())
+}
+
+/// Any model as JSON, for the `before` and `after` of an entry.
+pub fn to_json<T: Serialize>(value: &T) -> Option<Value>{
+    serde_json::to_value(value).ok()
+}
+
+/// An entry of the audit log.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Audit{
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub entity: String,
+    pub entity_id: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Filters of the audit log page, all of them optional: `actor`, `action`
+/// and `entity` must be equal, `from` and `to` are the first and last days.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuditFilter{
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub entity: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl AuditFilter{
+    pub fn from_query(params: &HashMap<String, String>) -> Self{
+        let get = |key: &str| params.get(key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        Self{
+            actor: get("actor"),
+            action: get("action"),
+            entity: get("entity"),
+            from: get("from"),
+            to: get("to"),
+        }
+    }
+
+    /// `WHERE` clause, starting with its keyword or empty, and the values to
+    /// bind to it in order.
+    fn where_clause(&self) -> (String, Vec<String>){
+        let mut conditions = Vec::new();
+        let mut binds = Vec::new();
+        // `to` is a day, entries of that day pass too
+        let columns = [("actor = $", "", &self.actor), ("action = $", "", &self.action),
+            ("entity = $", "", &self.entity), ("created_at >= date($", ")", &self.from),
+            ("created_at < date($", ", '+1 day')", &self.to)];
+        for (prefix, suffix, value) in columns{
+            if let Some(value) = value{
+                binds.push(value.to_string());
+                conditions.push(format!("{}{}{}", prefix, binds.len(), suffix));
+            }
+        }
+        if conditions.is_empty(){
+            ("".to_string(), binds)
+        }else{
+            (format!("WHERE {}", conditions.join(" AND ")), binds)
+        }
+    }
+
+    /// Query string with the filters, to build links.
+    pub fn to_query(&self) -> String{
+        let params: Vec<(&str, &String)> = [("actor", &self.actor),
+                ("action", &self.action), ("entity", &self.entity),
+                ("from", &self.from), ("to", &self.to)]
+            .into_iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+            .collect();
+        serde_urlencoded::to_string(params).unwrap_or_default()
+    }
+}
+
+impl Audit{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            actor: row.get("actor"),
+            action: row.get("action"),
+            entity: row.get("entity"),
+            entity_id: row.get("entity_id"),
+            before: row.get("before"),
+            after: row.get("after"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    /// Records a change made by the current actor. A failure is logged and
+    /// does not undo the change.
+    pub async fn record(pool: &SqlitePool, action: &str, entity: &str, entity_id: &str,
+            before: Option<Value>, after: Option<Value>){
+        info!("record");
+        let actor = current_actor();
+        debug!("{} {} {} {}", actor, action, entity, entity_id);
+        let sql = "INSERT INTO audit_log (actor, action, entity, entity_id, before,
+                   after, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)";
+        let result = query(sql)
+            .bind(&actor)
+            .bind(action)
+            .bind(entity)
+            .bind(entity_id)
+            .bind(before.map(|before| before.to_string()))
+            .bind(after.map(|after| after.to_string()))
+            .bind(Utc::now())
+            .execute(pool)
+            .await;
+        if let Err(e) = result{
+            error!("Can not record {} of {} {}: {}", action, entity, entity_id, e);
+        }
+    }
+
+    /// Number of entries that pass the filter.
+    pub async fn count(pool: &SqlitePool, filter: &AuditFilter) -> Result<i64, Error>{
+        info!("count");
+        let (where_clause, binds) = filter.where_clause();
+        let sql = format!("SELECT count(*) FROM audit_log {}", where_clause);
+        let mut sql_query = query(&sql);
+        for value in binds.iter(){
+            sql_query = sql_query.bind(value);
+        }
+        sql_query
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Entries that pass the filter, the newest first. Without `limit` all of
+    /// them are returned.
+    pub async fn read_page(pool: &SqlitePool, filter: &AuditFilter, limit: Option<i64>,
+            offset: i64) -> Result<Vec<Self>, Error>{
+        info!("read_page");
+        let (where_clause, binds) = filter.where_clause();
+        let sql = format!("SELECT * FROM audit_log {} ORDER BY id DESC LIMIT ${} OFFSET ${}",
+            where_clause, binds.len() + 1, binds.len() + 2);
+        let mut sql_query = query(&sql);
+        for value in binds.iter(){
+            sql_query = sql_query.bind(value);
+        }
+        sql_query
+            .bind(limit.unwrap_or(-1))
+            .bind(offset)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
@@ -0,0 +1,41 @@
+use sqlx::{
+    sqlite::SqlitePool,
+    query,
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::Error;
+
+/// Proof-of-work challenges already solved. A challenge stays here until it
+/// expires, when it would be rejected anyway.
+pub struct Challenge;
+
+impl Challenge{
+    /// Marks the challenge as solved. Returns `false` when it already was.
+    pub async fn spend(pool: &SqlitePool, nonce: &str, expires_at: DateTime<Utc>) -> Result<bool, Error>{
+        info!("spend");
+        let sql = "INSERT OR IGNORE INTO spent_challenges (nonce, expires_at)
+                   VALUES ($1, $2)";
+        Ok(query(sql)
+            .bind(nonce)
+            .bind(expires_at)
+            .execute(pool)
+            .await?
+            .rows_affected() > 0)
+    }
+
+    pub async fn purge_expired(pool: &SqlitePool) -> Result<u64, Error>{
+        info!("purge_expired");
+        let sql = "DELETE FROM spent_challenges WHERE expires_at < $1";
+        Ok(query(sql)
+            .bind(Utc::now())
+            .execute(pool)
+            .await?
+            .rows_affected())
+    }
+}
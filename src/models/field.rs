@@ -8,20 +8,83 @@ use sqlx::{
         SqliteRow
     },
     query,
-    Row
+    Executor,
+    Row,
+    Sqlite,
 };
 use chrono::{
     DateTime,
     Utc
 };
+use regex::Regex;
 use tracing::info;
 
 // my own uses
 use super::{
+    Audit,
     Error,
-    default_datetime
+    Form,
+    default_datatype,
+    default_datetime,
+    quote,
+    audit::to_json,
+    validation::compile_pattern,
 };
 
+/// An option of a `select`, `radio` or `checkboxes` field: the value stored
+/// and the label shown.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Choice {
+    pub value: String,
+    pub label: String,
+}
+
+/// Optional limits checked on every submission besides the `datatype` and
+/// `required` of the field.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Constraints {
+    #[serde(default)]
+    pub min_value: Option<String>,
+    #[serde(default)]
+    pub max_value: Option<String>,
+    #[serde(default)]
+    pub min_length: Option<i64>,
+    #[serde(default)]
+    pub max_length: Option<i64>,
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Largest file of a `file` field, in bytes.
+    #[serde(default)]
+    pub max_size: Option<i64>,
+    /// MIME types of the files of a `file` field, like `application/pdf` or
+    /// `image/*`. Any type when empty.
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    /// Options of a `select`, `radio` or `checkboxes` field, in order.
+    #[serde(default)]
+    pub options: Vec<Choice>,
+}
+
+/// A field to add to a form, as it is sent to the API.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewField{
+    pub name: String,
+    #[serde(default = "default_datatype")]
+    pub datatype: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub placeholder: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub unique: bool,
+    #[serde(flatten)]
+    pub constraints: Constraints,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Field {
     id: i64,
@@ -32,10 +95,19 @@ pub struct Field {
     placeholder: String,
     required: bool,
     unique: bool,
+    #[serde(flatten)]
+    constraints: Constraints,
+    /// The `pattern` of the constraints, compiled once.
+    #[serde(skip)]
+    regex: Option<Regex>,
+    #[serde(default)]
+    position: i64,
     #[serde(default = "default_datetime")]
     created_at: DateTime<Utc>,
     #[serde(default = "default_datetime")]
     updated_at: DateTime<Utc>,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Field{
@@ -43,39 +115,120 @@ impl Field{
         self.id
     }
 
+    pub fn get_form_id(&self) -> i64{
+        self.form_id
+    }
+
+    pub fn get_name(&self) -> &str{
+        &self.name
+    }
+
+    pub fn get_datatype(&self) -> &str{
+        &self.datatype
+    }
+
+    pub fn get_label(&self) -> &str{
+        &self.label
+    }
+
+    pub fn is_required(&self) -> bool{
+        self.required
+    }
+
+    pub fn is_unique(&self) -> bool{
+        self.unique
+    }
+
+    pub fn get_constraints(&self) -> &Constraints{
+        &self.constraints
+    }
+
+    pub fn get_pattern(&self) -> Option<&Regex>{
+        self.regex.as_ref()
+    }
+
+    pub fn get_deleted_at(&self) -> Option<DateTime<Utc>>{
+        self.deleted_at
+    }
+
+    pub fn set_datatype(&mut self, datatype: String){
+        self.datatype = datatype;
+    }
+
+    pub fn set_label(&mut self, label: String){
+        self.label = label;
+    }
+
+    pub fn set_placeholder(&mut self, placeholder: String){
+        self.placeholder = placeholder;
+    }
+
+    pub fn set_required(&mut self, required: bool){
+        self.required = required;
+    }
+
+    pub fn set_unique(&mut self, unique: bool){
+        self.unique = unique;
+    }
+
+    pub fn set_constraints(&mut self, constraints: Constraints){
+        self.regex = constraints.pattern.as_deref().and_then(compile_pattern);
+        self.constraints = constraints;
+    }
+
     fn from_row(row: SqliteRow) -> Self{
         info!("from_row");
+        let constraints = Constraints{
+            min_value: row.get("min_value"),
+            max_value: row.get("max_value"),
+            min_length: row.get("min_length"),
+            max_length: row.get("max_length"),
+            pattern: row.get("pattern"),
+            allowed: serde_json::from_str(row.get("allowed"))
+                .unwrap_or_default(),
+            max_size: row.get("max_size"),
+            mime_types: serde_json::from_str(row.get("mime_types"))
+                .unwrap_or_default(),
+            options: serde_json::from_str(row.get("options"))
+                .unwrap_or_default(),
+        };
         Self{
             id: row.get("id"),
             form_id: row.get("form_id"),
-            name: row.get("key"),
-            datatype: row.get("value"),
-            label: row.get("created_at"),
-            placeholder: row.get("updated_at"),
+            name: row.get("name"),
+            datatype: row.get("datatype"),
+            label: row.get("label"),
+            placeholder: row.get("placeholder"),
             required: row.get("required"),
             unique: row.get("unique"),
+            regex: constraints.pattern.as_deref().and_then(compile_pattern),
+            constraints,
+            position: row.get("position"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            deleted_at: row.get("deleted_at"),
         }
     }
 
-    pub async fn new(pool: &SqlitePool, form_id: i64, name: String,
-                     datatype: String, label: String, placeholder: String,
-                     required: bool, unique: bool) -> Result<Self, Error>{
+    pub async fn new(pool: &SqlitePool, form_id: i64, field: NewField) -> Result<Self, Error>{
         info!("new");
         let created_at = Utc::now();
-        let updated_at = created_at.clone();
+        let updated_at = created_at;
         let mut field = Self{
             id: -1,
             form_id,
-            name,
-            datatype,
-            label,
-            placeholder,
-            required,
-            unique,
+            name: field.name,
+            datatype: field.datatype,
+            label: field.label,
+            placeholder: field.placeholder,
+            required: field.required,
+            unique: field.unique,
+            regex: field.constraints.pattern.as_deref().and_then(compile_pattern),
+            constraints: field.constraints,
+            position: 0,
             created_at,
             updated_at,
+            deleted_at: None,
         };
         field.save(pool).await
     }
@@ -93,36 +246,61 @@ impl Field{
         }
     }
 
+    #[allow(dead_code)]
     pub async fn delete (&mut self, pool: &SqlitePool) -> Result<Self, Error>{
         info!("remove");
         Self::remove(pool, self.id).await
     }
 
 
+    /// Stores a new field and adds its column to the table of its form. If
+    /// the column can not be added the field is not stored either.
     pub async fn create(pool: &SqlitePool, field: &Self) -> Result<Self, Error>{
         info!("create");
-        let sql = "INSERT INTO fields (form_id, name, datetype, label,
-                   placeholder, required, unique, created_at, updated_at)
-                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *";
-        query(sql)
-            .bind(&field.form_id)
+        let form = Form::read(pool, field.form_id).await?;
+        let mut tx = pool.begin().await?;
+        let sql = "INSERT INTO fields (form_id, name, datatype, label,
+                   placeholder, required, \"unique\", min_value, max_value,
+                   min_length, max_length, pattern, allowed, max_size,
+                   mime_types, options, position, created_at, updated_at)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12,
+                   $13, $14, $15, $16, (SELECT COALESCE(MAX(position), 0) + 1
+                   FROM fields WHERE form_id = $1), $17, $18) RETURNING *";
+        let allowed = serde_json::to_string(&field.constraints.allowed)?;
+        let mime_types = serde_json::to_string(&field.constraints.mime_types)?;
+        let options = serde_json::to_string(&field.constraints.options)?;
+        let created = query(sql)
+            .bind(field.form_id)
             .bind(&field.name)
             .bind(&field.datatype)
             .bind(&field.label)
             .bind(&field.placeholder)
-            .bind(&field.required)
-            .bind(&field.unique)
-            .bind(&field.created_at)
-            .bind(&field.updated_at)
+            .bind(field.required)
+            .bind(field.unique)
+            .bind(&field.constraints.min_value)
+            .bind(&field.constraints.max_value)
+            .bind(field.constraints.min_length)
+            .bind(field.constraints.max_length)
+            .bind(&field.constraints.pattern)
+            .bind(&allowed)
+            .bind(field.constraints.max_size)
+            .bind(&mime_types)
+            .bind(&options)
+            .bind(field.created_at)
+            .bind(field.updated_at)
             .map(Self::from_row)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| e.into())
+            .fetch_one(&mut tx)
+            .await?;
+        form.sync_table(&mut tx).await?;
+        tx.commit().await?;
+        Audit::record(pool, "create", "field", &created.id.to_string(),
+            None, to_json(&created)).await;
+        Ok(created)
     }
 
     pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
         info!("read");
-        let sql = "SELECT * FROM fields WHERE id = $1";
+        let sql = "SELECT * FROM fields WHERE id = $1 AND deleted_at IS NULL";
         query(sql)
             .bind(id)
             .map(Self::from_row)
@@ -131,9 +309,10 @@ impl Field{
             .map_err(|e| e.into())
     }
 
+    #[allow(dead_code)]
     pub async fn read_by_name(pool: &SqlitePool, name: &str) -> Result<Self, Error>{
         info!("read_by_name");
-        let sql = "SELECT * FROM fields WHERE name = $1";
+        let sql = "SELECT * FROM fields WHERE name = $1 AND deleted_at IS NULL";
         query(sql)
             .bind(name)
             .map(Self::from_row)
@@ -144,18 +323,47 @@ impl Field{
 
     pub async fn read_by_form_id(pool: &SqlitePool, form_id: i64) -> Result<Vec<Self>, Error>{
         info!("read_by_form_id");
-        let sql = "SELECT * FROM fields WHERE form_id = $1";
+        let sql = "SELECT * FROM fields WHERE form_id = $1 AND deleted_at IS NULL
+                   ORDER BY position, id";
+        query(sql)
+            .bind(form_id)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Fields of a form, those in the trash included, as they all have a
+    /// column in the table of the form.
+    pub async fn read_all_by_form_id<'c, E>(executor: E, form_id: i64) -> Result<Vec<Self>, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        info!("read_all_by_form_id");
+        let sql = "SELECT * FROM fields WHERE form_id = $1 ORDER BY position, id";
         query(sql)
             .bind(form_id)
+            .map(Self::from_row)
+            .fetch_all(executor)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Fields in the trash of the forms that are not, the last deleted first.
+    pub async fn read_deleted(pool: &SqlitePool) -> Result<Vec<Self>, Error>{
+        info!("read_deleted");
+        let sql = "SELECT fields.* FROM fields JOIN forms ON forms.id = fields.form_id
+                   WHERE fields.deleted_at IS NOT NULL AND forms.deleted_at IS NULL
+                   ORDER BY fields.deleted_at DESC";
+        query(sql)
             .map(Self::from_row)
             .fetch_all(pool)
             .await
             .map_err(|e| e.into())
     }
 
+    #[allow(dead_code)]
     pub async fn read_all(pool: &SqlitePool) -> Result<Vec<Self>, Error>{
         info!("read_all");
-        let sql = "SELECT * FROM fields";
+        let sql = "SELECT * FROM fields WHERE deleted_at IS NULL";
         query(sql)
             .map(Self::from_row)
             .fetch_all(pool)
@@ -163,46 +371,165 @@ impl Field{
             .map_err(|e| e.into())
     }
 
+    /// Stores the changes of a field and brings the table of its form in
+    /// line with them. If the table can not be changed, for instance when a
+    /// column with repeated values becomes unique, the field is left as it
+    /// was.
     pub async fn update(pool: &SqlitePool, field: &Self) -> Result<Self, Error>{
         info!("update");
+        let before = Self::read(pool, field.id).await?;
+        let form = Form::read(pool, before.form_id).await?;
         let updated_at = Utc::now();
-        let sql = "UPDATE fields SET datatype = $1, label = $2, placeholder = $3, required = $4, unique = $5, updated_at = $6 WHERE id = $7 RETURNING *";
-        query(sql)
+        let sql = "UPDATE fields SET datatype = $1, label = $2, placeholder = $3, required = $4, \"unique\" = $5, min_value = $6, max_value = $7, min_length = $8, max_length = $9, pattern = $10, allowed = $11, max_size = $12, mime_types = $13, options = $14, updated_at = $15 WHERE id = $16 RETURNING *";
+        let allowed = serde_json::to_string(&field.constraints.allowed)?;
+        let mime_types = serde_json::to_string(&field.constraints.mime_types)?;
+        let options = serde_json::to_string(&field.constraints.options)?;
+        let mut tx = pool.begin().await?;
+        let updated = query(sql)
             .bind(&field.datatype)
             .bind(&field.label)
             .bind(&field.placeholder)
-            .bind(&field.unique)
+            .bind(field.required)
+            .bind(field.unique)
+            .bind(&field.constraints.min_value)
+            .bind(&field.constraints.max_value)
+            .bind(field.constraints.min_length)
+            .bind(field.constraints.max_length)
+            .bind(&field.constraints.pattern)
+            .bind(&allowed)
+            .bind(field.constraints.max_size)
+            .bind(&mime_types)
+            .bind(&options)
             .bind(updated_at)
-            .bind(&field.id)
+            .bind(field.id)
             .map(Self::from_row)
-            .fetch_one(pool)
-            .await
-        .map_err(|e| e.into())
+            .fetch_one(&mut tx)
+            .await?;
+        form.sync_table(&mut tx).await?;
+        tx.commit().await?;
+        Audit::record(pool, "update", "field", &updated.id.to_string(),
+            to_json(&before), to_json(&updated)).await;
+        Ok(updated)
+    }
+
+    /// Moves the field `offset` places inside its form, swapping positions
+    /// with the field that is there.
+    pub async fn move_by(pool: &SqlitePool, id: i64, offset: i64) -> Result<(), Error>{
+        info!("move_by");
+        let field = Self::read(pool, id).await?;
+        let fields = Self::read_by_form_id(pool, field.form_id).await?;
+        let index = match fields.iter().position(|other| other.id == id){
+            Some(index) => index as i64,
+            None => return Ok(()),
+        };
+        let target = index + offset;
+        if target < 0 || target >= fields.len() as i64{
+            return Ok(());
+        }
+        let mut tx = pool.begin().await?;
+        let sql = "UPDATE fields SET position = $1 WHERE id = $2";
+        for (position, other) in fields.iter().enumerate(){
+            let position = position as i64;
+            let new_position = if position == index{
+                target
+            }else if position == target{
+                index
+            }else{
+                position
+            };
+            query(sql)
+                .bind(new_position + 1)
+                .bind(other.id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        let moved = Self::read(pool, id).await?;
+        Audit::record(pool, "move", "field", &id.to_string(),
+            to_json(&field), to_json(&moved)).await;
+        Ok(())
     }
 
+    /// Moves the field to the trash. Its column, and the values of the
+    /// submissions in it, are kept until it is purged.
     pub async fn remove(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
         info!("delete");
-        let sql = "DELETE FROM fields WHERE id = $1 RETURNING *";
-        query(sql)
+        let sql = "UPDATE fields SET deleted_at = $1 WHERE id = $2
+                   AND deleted_at IS NULL RETURNING *";
+        let removed = query(sql)
+            .bind(Utc::now())
             .bind(id)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-        .map_err(|e| e.into())
+            .await?;
+        Audit::record(pool, "delete", "field", &removed.id.to_string(),
+            None, to_json(&removed)).await;
+        Ok(removed)
+    }
+
+    /// Takes the field out of the trash.
+    pub async fn restore(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("restore");
+        // Other fields may have taken its position meanwhile, so it goes last
+        let sql = "UPDATE fields SET deleted_at = NULL, position = (SELECT
+                   COALESCE(MAX(position), 0) + 1 FROM fields AS other WHERE
+                   other.form_id = fields.form_id AND other.deleted_at IS NULL)
+                   WHERE id = $1 AND deleted_at IS NOT NULL RETURNING *";
+        let restored = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "restore", "field", &restored.id.to_string(),
+            None, to_json(&restored)).await;
+        Ok(restored)
+    }
+
+    /// Deletes for good a field in the trash and drops its column, with the
+    /// uploads of a `file` field, all or none.
+    pub async fn purge(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("purge");
+        let mut tx = pool.begin().await?;
+        let sql = "DELETE FROM fields WHERE id = $1 AND deleted_at IS NOT NULL
+                   RETURNING *";
+        let purged = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(&mut tx)
+            .await?;
+        let form = Form::read_any(&mut tx, purged.form_id).await?;
+        if purged.datatype == "file"{
+            let sql = format!("DELETE FROM uploads WHERE form_id = $1 AND id IN
+                               (SELECT CAST({} AS INTEGER) FROM {})",
+                quote(&purged.name), quote(&form.name));
+            query(&sql)
+                .bind(form.id)
+                .execute(&mut tx)
+                .await?;
+        }
+        form.sync_table(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Audit::record(pool, "purge", "field", &purged.id.to_string(),
+            to_json(&purged), None).await;
+        Ok(purged)
     }
 
     pub fn to_sqlite(&self) -> &str{
         match self.datatype.as_str(){
             "checkbox"       => "BOOLEAN",
+            "checkboxes"     => "TEXT",
             "color"          => "TEXT",
             "date"           => "DATE",
             "datetime-local" => "DATETIME",
             "email"          => "TEXT",
+            "file"           => "INTEGER",
             "month"          => "INTEGER",
             "number"         => "REAL",
             "password"       => "TEXT",
-            "radio"          => "BOOLEAN",
+            "radio"          => "TEXT",
             "range"          => "INTEGER",
+            "select"         => "TEXT",
             "tel"            => "TEXT",
             "text"           => "TEXT",
             "time"           => "TIME",
@@ -211,4 +538,175 @@ impl Field{
             _                => "TEXT",
         }
     }
+
+    /// A value of this field as read from its table, typed after the SQLite
+    /// type of the field: numbers as numbers, booleans as booleans and
+    /// everything else, dates and times included, as ISO strings.
+    pub fn to_typed(&self, value: &serde_json::Value) -> serde_json::Value{
+        use serde_json::Value;
+        let text = match value{
+            Value::String(text) => text.as_str(),
+            Value::Null => return Value::Null,
+            _ => "",
+        };
+        if self.datatype == "checkboxes"{
+            return match value{
+                Value::Array(_) => value.clone(),
+                _ => Value::from(Self::chosen(text)),
+            };
+        }
+        match self.to_sqlite(){
+            "BOOLEAN" => match value{
+                Value::Number(number) => Value::Bool(number.as_f64() != Some(0.0)),
+                Value::Bool(boolean) => Value::Bool(*boolean),
+                _ => Value::Bool(matches!(text, "on" | "true" | "1")),
+            },
+            "REAL" | "INTEGER" => match value{
+                Value::Number(_) => value.clone(),
+                _ if text.is_empty() => Value::Null,
+                _ => text.parse::<i64>()
+                    .map(Value::from)
+                    .or_else(|_| text.parse::<f64>().map(Value::from))
+                    .unwrap_or_else(|_| Value::from(text)),
+            },
+            _ => match value{
+                Value::String(_) => value.clone(),
+                value => Value::from(value.to_string()),
+            },
+        }
+    }
+
+    /// Checks if the values of this field are chosen from its options.
+    pub fn has_options(&self) -> bool{
+        matches!(self.datatype.as_str(), "select" | "radio" | "checkboxes")
+    }
+
+    /// Values chosen in a `checkboxes` field, stored as a JSON array.
+    pub fn chosen(value: &str) -> Vec<String>{
+        serde_json::from_str(value).unwrap_or_default()
+    }
+
+    /// Label of the option with this value, or the value itself if there is
+    /// no such option.
+    fn option_label(&self, value: &str) -> String{
+        self.constraints.options.iter()
+            .find(|option| option.value == value)
+            .map(|option| option.label.clone())
+            .unwrap_or_else(|| value.to_string())
+    }
+
+    /// A value of this field as text for people, with the labels of the
+    /// options chosen in choice fields.
+    pub fn to_label(&self, value: &serde_json::Value) -> String{
+        use serde_json::Value;
+        match value{
+            Value::Null => "".to_string(),
+            Value::Array(items) => items.iter()
+                .map(|item| self.option_label(item.as_str().unwrap_or_default()))
+                .collect::<Vec<String>>()
+                .join(", "),
+            Value::String(text) if self.datatype == "checkboxes" =>
+                self.to_label(&Value::from(Self::chosen(text))),
+            Value::String(text) if matches!(self.datatype.as_str(), "select" | "radio") =>
+                self.option_label(text),
+            Value::String(text) => text.to_string(),
+            value => value.to_string(),
+        }
+    }
+
+    /// Column definition of this field in the table of its form.
+    pub fn to_column(&self) -> String{
+        if self.unique{
+            format!("{} {} UNIQUE", quote(&self.name), self.to_sqlite())
+        }else{
+            format!("{} {}", quote(&self.name), self.to_sqlite())
+        }
+    }
+}
+
+#[cfg(test)]
+impl Field{
+    /// A field that is not stored, to test the checks on its values.
+    pub fn sample(name: &str, datatype: &str, required: bool, constraints: Constraints) -> Self{
+        Self{
+            id: 1,
+            form_id: 1,
+            name: name.to_string(),
+            datatype: datatype.to_string(),
+            label: name.to_string(),
+            placeholder: String::new(),
+            required,
+            unique: false,
+            regex: constraints.pattern.as_deref().and_then(compile_pattern),
+            constraints,
+            position: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::collections::HashMap;
+    use crate::migrations::{test_field, test_form};
+    use crate::models::Origin;
+
+    #[actix_web::test]
+    async fn update_keeps_the_field_when_the_table_can_not_follow(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, _) = test_form(&pool, "contact", vec![]).await;
+        let mut field = Field::new(&pool, form.id, test_field("email", "email"))
+            .await
+            .unwrap();
+        let data = HashMap::from([("email".to_string(), "me@example.com".to_string())]);
+        for _ in 0..2{
+            form.insert(&pool, &[field.clone()], &data, &Origin::default()).await.unwrap();
+        }
+        field.set_unique(true);
+        assert!(field.save(&pool).await.is_err());
+        let stored = Field::read(&pool, field.get_id()).await.unwrap();
+        assert!(!stored.is_unique());
+        // The table still follows the fields added after the failure
+        Field::new(&pool, form.id, test_field("name", "text"))
+            .await
+            .unwrap();
+        let fields = form.get_fields(&pool).await.unwrap();
+        let data = HashMap::from([("name".to_string(), "Me".to_string())]);
+        form.insert(&pool, &fields, &data, &Origin::default()).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn restore_puts_the_field_after_the_others(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, _) = test_form(&pool, "contact", vec![]).await;
+        let mut ids = Vec::new();
+        for name in ["a", "b", "c"]{
+            ids.push(Field::new(&pool, form.id, test_field(name, "text")).await.unwrap().get_id());
+        }
+        Field::remove(&pool, ids[0]).await.unwrap();
+        // Moving the others renumbers them from the first position
+        Field::move_by(&pool, ids[2], -1).await.unwrap();
+        Field::restore(&pool, ids[0]).await.unwrap();
+        let names: Vec<String> = Field::read_by_form_id(&pool, form.id).await.unwrap()
+            .iter()
+            .map(|field| field.get_name().to_string())
+            .collect();
+        assert_eq!(names, ["c", "b", "a"]);
+    }
+
+    #[actix_web::test]
+    async fn purge_works_in_a_form_in_the_trash(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, _) = test_form(&pool, "contact", vec![]).await;
+        let field = Field::new(&pool, form.id, test_field("cv", "file")).await.unwrap();
+        Field::new(&pool, form.id, test_field("name", "text")).await.unwrap();
+        Field::remove(&pool, field.get_id()).await.unwrap();
+        Form::remove(&pool, form.id).await.unwrap();
+        assert!(Field::purge(&pool, field.get_id()).await.is_ok());
+        let fields = Field::read_all_by_form_id(&pool, form.id).await.unwrap();
+        assert_eq!(fields.len(), 1);
+    }
 }
@@ -0,0 +1,208 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+// my own uses
+use super::{
+    Field,
+    METADATA,
+    quote,
+};
+
+/// How a field can be filtered.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind{
+    /// Text that contains the value
+    Contains,
+    /// Values between two limits, both optional
+    Range,
+    /// Checked or not
+    Boolean,
+}
+
+impl FilterKind{
+    pub fn of(field: &Field) -> Self{
+        match field.get_datatype(){
+            "checkbox" => FilterKind::Boolean,
+            "date" | "datetime-local" | "time" | "month" | "week" | "number"
+                | "range" => FilterKind::Range,
+            _ => FilterKind::Contains,
+        }
+    }
+}
+
+/// Filters and order of the submissions of a form, read from the query
+/// string of the results view. For every field `name` there can be
+/// `q_name` (contains, or `true`/`false` for booleans) and `from_name`,
+/// `to_name` (ranges). Columns are only taken from the fields of the form
+/// and its metadata, values are always bound as parameters.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Filter{
+    pub sort: Option<String>,
+    pub descending: bool,
+    pub values: HashMap<String, String>,
+}
+
+fn escape_like(value: &str) -> String{
+    value.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+impl Filter{
+    pub fn from_query(fields: &[Field], params: &HashMap<String, String>) -> Self{
+        let sort = params.get("sort")
+            .filter(|sort| fields.iter().any(|field| field.get_name() == sort.as_str()) ||
+                METADATA.iter().any(|(name, _)| name == sort))
+            .cloned();
+        let descending = params.get("order")
+            .map(|order| order == "desc")
+            .unwrap_or(false);
+        let mut values = HashMap::new();
+        for field in fields{
+            for prefix in ["q_", "from_", "to_"]{
+                let key = format!("{}{}", prefix, field.get_name());
+                if let Some(value) = params.get(&key){
+                    if !value.trim().is_empty(){
+                        values.insert(key, value.trim().to_string());
+                    }
+                }
+            }
+        }
+        Self{
+            sort,
+            descending,
+            values,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.values.is_empty()
+    }
+
+    /// `WHERE` clause, starting with its keyword, and the values to bind to
+    /// it in order. Submissions in the trash never pass.
+    pub fn where_clause(&self, fields: &[Field]) -> (String, Vec<String>){
+        let mut conditions = vec!["deleted_at IS NULL".to_string()];
+        let mut binds = Vec::new();
+        for field in fields{
+            let column = quote(field.get_name());
+            let get = |prefix: &str| self.values.get(&format!("{}{}", prefix, field.get_name()));
+            match FilterKind::of(field){
+                FilterKind::Contains => {
+                    if let Some(value) = get("q_"){
+                        binds.push(format!("%{}%", escape_like(value)));
+                        conditions.push(format!("{} LIKE ${} ESCAPE '\\'", column, binds.len()));
+                    }
+                },
+                FilterKind::Boolean => {
+                    match get("q_").map(|value| value.as_str()){
+                        Some("true") => conditions.push(format!("{} = 1", column)),
+                        Some("false") => conditions.push(format!("({} = 0 OR {} IS NULL)", column, column)),
+                        _ => {},
+                    }
+                },
+                FilterKind::Range => {
+                    if let Some(value) = get("from_"){
+                        binds.push(value.to_string());
+                        conditions.push(format!("{} >= ${}", column, binds.len()));
+                    }
+                    if let Some(value) = get("to_"){
+                        binds.push(value.to_string());
+                        conditions.push(format!("{} <= ${}", column, binds.len()));
+                    }
+                },
+            }
+        }
+        (format!("WHERE {}", conditions.join(" AND ")), binds)
+    }
+
+    /// `ORDER BY` clause. Submissions are always sorted last by id, so pages
+    /// are stable.
+    pub fn order_clause(&self) -> String{
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        match &self.sort{
+            Some(sort) => format!("ORDER BY {} {}, id {}", quote(sort), direction, direction),
+            None => format!("ORDER BY id {}", direction),
+        }
+    }
+
+    /// Query string with the filters and the order, to build links.
+    pub fn to_query(&self) -> String{
+        let mut params: Vec<(String, String)> = self.values.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        params.sort();
+        if let Some(sort) = &self.sort{
+            params.push(("sort".to_string(), sort.to_string()));
+        }
+        if self.descending{
+            params.push(("order".to_string(), "desc".to_string()));
+        }
+        serde_urlencoded::to_string(params).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::Constraints;
+
+    fn field(name: &str, datatype: &str) -> Field{
+        Field::sample(name, datatype, false, Constraints::default())
+    }
+
+    fn filter(values: &[(&str, &str)]) -> Filter{
+        Filter{
+            values: values.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn where_clause_leaves_out_the_trash(){
+        let fields = [field("name", "text")];
+        let (clause, binds) = filter(&[("q_other", "value")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL");
+        assert!(binds.is_empty());
+    }
+
+    #[test]
+    fn where_clause_escapes_contains(){
+        let fields = [field("name", "text")];
+        let (clause, binds) = filter(&[("q_name", "50%_a\\b")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL AND \"name\" LIKE $1 ESCAPE '\\'");
+        assert_eq!(binds, vec!["%50\\%\\_a\\\\b%".to_string()]);
+    }
+
+    #[test]
+    fn where_clause_binds_ranges_in_order(){
+        let fields = [field("age", "number"), field("born", "date")];
+        let (clause, binds) = filter(&[("from_age", "18"), ("to_age", "65"),
+            ("to_born", "2000-01-01")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL AND \"age\" >= $1 AND \"age\" <= $2 \
+            AND \"born\" <= $3");
+        assert_eq!(binds, vec!["18".to_string(), "65".to_string(), "2000-01-01".to_string()]);
+    }
+
+    #[test]
+    fn where_clause_checks_booleans_without_binds(){
+        let fields = [field("agree", "checkbox")];
+        let (clause, binds) = filter(&[("q_agree", "true")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL AND \"agree\" = 1");
+        assert!(binds.is_empty());
+        let (clause, _) = filter(&[("q_agree", "false")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL AND (\"agree\" = 0 OR \"agree\" IS NULL)");
+        let (clause, _) = filter(&[("q_agree", "1; DROP TABLE forms")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL");
+    }
+
+    #[test]
+    fn where_clause_quotes_columns(){
+        let fields = [field("a\"b", "text")];
+        let (clause, _) = filter(&[("q_a\"b", "x")]).where_clause(&fields);
+        assert_eq!(clause, "WHERE deleted_at IS NULL AND \"a\"\"b\" LIKE $1 ESCAPE '\\'");
+    }
+}
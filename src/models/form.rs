@@ -1,35 +1,74 @@
 use serde::{Serialize, Deserialize};
 use sqlx::{
     sqlite::{
+        SqliteArguments,
         SqlitePool,
         SqliteRow
     },
     query,
-    Row
+    query::Query,
+    Encode,
+    Executor,
+    Row,
+    Sqlite,
+    Transaction,
+    Type,
 };
 use chrono::{
     DateTime,
     Utc
 };
-use tracing::info;
+use tracing::{info, debug};
+use std::collections::HashMap;
 
 // my own uses
 use super::{
+    Audit,
     Error,
     Field,
-    default_datetime
+    Filter,
+    default_datetime,
+    default_template,
+    quote,
+    audit::to_json,
 };
 
+/// Columns every table of submissions has besides the fields of its form,
+/// with their definitions. Old submissions get a fresh `uuid` when their
+/// table is rebuilt.
+pub const METADATA: [(&str, &str); 6] = [
+    ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+    ("uuid", "TEXT NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || \
+        hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || \
+        substr('89ab', 1 + (abs(random()) % 4), 1) || \
+        substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))))"),
+    ("submitted_at", "DATETIME"),
+    ("ip", "TEXT"),
+    ("user_agent", "TEXT"),
+    ("deleted_at", "DATETIME"),
+];
+
+/// Where a submission comes from.
+#[derive(Debug, Clone, Default)]
+pub struct Origin{
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Form{
     pub id: i64,
     pub name: String,
     pub title: String,
     pub instructions: String,
+    #[serde(default = "default_template")]
+    pub template: String,
     #[serde(default = "default_datetime")]
     created_at: DateTime<Utc>,
     #[serde(default = "default_datetime")]
     updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 
@@ -41,20 +80,24 @@ impl Form {
             name: row.get("name"),
             title: row.get("title"),
             instructions: row.get("instructions"),
+            template: row.get("template"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            deleted_at: row.get("deleted_at"),
         }
     }
-    pub async fn new(pool: &SqlitePool, name: String, title: String, instructions: String) -> Result<Self, Error>{
+    pub async fn new(pool: &SqlitePool, name: String, title: String, instructions: String, template: String) -> Result<Self, Error>{
         let created_at = Utc::now();
-        let updated_at = created_at.clone();
+        let updated_at = created_at;
         let mut form = Self{
             id: -1,
             name,
             title,
             instructions,
+            template,
             created_at,
             updated_at,
+            deleted_at: None,
         };
         form.save(pool).await
     }
@@ -71,36 +114,40 @@ impl Form {
         }
     }
 
+    #[allow(dead_code)]
     pub async fn delete(&mut self, pool: &SqlitePool) -> Result<Self, Error>{
         info!("remove");
         Self::remove(pool, self.id).await
     }
 
-    pub async fn get_fields(self, pool: &SqlitePool) -> Result<Vec<Field>, Error>{
+    pub async fn get_fields(&self, pool: &SqlitePool) -> Result<Vec<Field>, Error>{
         info!("get_fields");
         Field::read_by_form_id(pool, self.id).await
     }
 
     pub async fn create(pool: &SqlitePool, form: &Self) -> Result<Self, Error>{
         info!("create");
-        let sql = "INSERT INTO forms (name, title, instructions,
-                   created_at, updated_at) VALUES ($1, $2, $3, $4, $5)
+        let sql = "INSERT INTO forms (name, title, instructions, template,
+                   created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)
                    RETURNING *";
-        query(sql)
+        let created = query(sql)
             .bind(&form.name)
             .bind(&form.title)
             .bind(&form.instructions)
-            .bind(&form.created_at)
-            .bind(&form.updated_at)
+            .bind(&form.template)
+            .bind(form.created_at)
+            .bind(form.updated_at)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-            .map_err(|e| e.into())
+            .await?;
+        Audit::record(pool, "create", "form", &created.id.to_string(),
+            None, to_json(&created)).await;
+        Ok(created)
     }
 
     pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
         info!("read");
-        let sql = "SELECT * FROM forms WHERE id = $1";
+        let sql = "SELECT * FROM forms WHERE id = $1 AND deleted_at IS NULL";
         query(sql)
             .bind(id)
             .map(Self::from_row)
@@ -109,9 +156,22 @@ impl Form {
             .map_err(|e| e.into())
     }
 
+    /// A form, in the trash or not.
+    pub async fn read_any<'c, E>(executor: E, id: i64) -> Result<Self, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        info!("read_any");
+        let sql = "SELECT * FROM forms WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(executor)
+            .await
+            .map_err(|e| e.into())
+    }
+
     pub async fn read_by_name(pool: &SqlitePool, name: &str) -> Result<Self, Error>{
         info!("read_by_name");
-        let sql = "SELECT * FROM forms WHERE name = $1";
+        let sql = "SELECT * FROM forms WHERE name = $1 AND deleted_at IS NULL";
         query(sql)
             .bind(name)
             .map(Self::from_row)
@@ -122,7 +182,7 @@ impl Form {
 
     pub async fn read_all(pool: &SqlitePool) -> Result<Vec<Self>, Error>{
         info!("read_all");
-        let sql = "SELECT * FROM forms";
+        let sql = "SELECT * FROM forms WHERE deleted_at IS NULL";
         query(sql)
             .map(Self::from_row)
             .fetch_all(pool)
@@ -132,43 +192,130 @@ impl Form {
 
     pub async fn update(pool: &SqlitePool, form: &Self) -> Result<Self, Error>{
         info!("update");
+        let before = Self::read(pool, form.id).await?;
         let updated_at = Utc::now();
         let sql = "UPDATE forms SET title = $1, instructions = $2,
-                   updated_at = $3 WHERE id = $4 RETURNING *";
-        query(sql)
+                   template = $3, updated_at = $4 WHERE id = $5 RETURNING *";
+        let updated = query(sql)
             .bind(&form.title)
             .bind(&form.instructions)
+            .bind(&form.template)
             .bind(updated_at)
             .bind(form.id)
             .map(Self::from_row)
             .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "update", "form", &updated.id.to_string(),
+            to_json(&before), to_json(&updated)).await;
+        Ok(updated)
+    }
+
+    /// Forms in the trash, the last deleted first.
+    pub async fn read_deleted(pool: &SqlitePool) -> Result<Vec<Self>, Error>{
+        info!("read_deleted");
+        let sql = "SELECT * FROM forms WHERE deleted_at IS NOT NULL
+                   ORDER BY deleted_at DESC";
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool)
             .await
-        .map_err(|e| e.into())
+            .map_err(|e| e.into())
     }
 
+    /// Moves the form to the trash. Its fields and submissions are kept
+    /// until it is purged.
     pub async fn remove(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
         info!("remove");
-        let sql = "DELETE forms WHERE id = $1 RETURNING *";
-        query(sql)
+        let sql = "UPDATE forms SET deleted_at = $1 WHERE id = $2
+                   AND deleted_at IS NULL RETURNING *";
+        let removed = query(sql)
+            .bind(Utc::now())
             .bind(id)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-        .map_err(|e| e.into())
+            .await?;
+        Audit::record(pool, "delete", "form", &removed.id.to_string(),
+            None, to_json(&removed)).await;
+        Ok(removed)
+    }
+
+    /// Takes the form out of the trash.
+    pub async fn restore(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("restore");
+        let sql = "UPDATE forms SET deleted_at = NULL WHERE id = $1
+                   AND deleted_at IS NOT NULL RETURNING *";
+        let restored = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "restore", "form", &restored.id.to_string(),
+            None, to_json(&restored)).await;
+        Ok(restored)
+    }
+
+    /// Deletes for good a form in the trash, with its fields and the table
+    /// of its submissions.
+    pub async fn purge(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("purge");
+        let mut tx = pool.begin().await?;
+        let sql = "DELETE FROM forms WHERE id = $1 AND deleted_at IS NOT NULL
+                   RETURNING *";
+        let form = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(&mut tx)
+            .await?;
+        query("DELETE FROM fields WHERE form_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query("DELETE FROM notifications WHERE form_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query("DELETE FROM protections WHERE form_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query("DELETE FROM webhook_deliveries WHERE webhook_id IN
+               (SELECT id FROM webhooks WHERE form_id = $1)")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query("DELETE FROM webhooks WHERE form_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query("DELETE FROM uploads WHERE form_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        query(&form.drop(&quote(&form.name)))
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Audit::record(pool, "purge", "form", &form.id.to_string(),
+            to_json(&form), None).await;
+        Ok(form)
     }
 
+    #[allow(dead_code)]
     fn to_sqlite(datatype: &str) -> &str{
         match datatype{
             "checkbox"       => "BOOLEAN",
+            "checkboxes"     => "TEXT",
             "color"          => "TEXT",
             "date"           => "DATE",
             "datetime-local" => "DATETIME",
             "email"          => "TEXT",
+            "file"           => "INTEGER",
             "month"          => "INTEGER",
             "number"         => "REAL",
             "password"       => "TEXT",
-            "radio"          => "BOOLEAN",
+            "radio"          => "TEXT",
             "range"          => "INTEGER",
+            "select"         => "TEXT",
             "tel"            => "TEXT",
             "text"           => "TEXT",
             "time"           => "TIME",
@@ -181,5 +328,524 @@ impl Form {
     pub fn drop(&self, name: &str) -> String{
         format!("DROP TABLE IF EXISTS {};", name)
     }
+
+    /// Columns of the table that stores the submissions of this form, as
+    /// pairs of name and SQLite type, read from the database.
+    async fn live_columns<'c, E>(&self, executor: E) -> Result<Vec<(String, String)>, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        info!("live_columns");
+        let sql = format!("PRAGMA table_info({})", quote(&self.name));
+        query(&sql)
+            .map(|row: SqliteRow| -> (String, String) {
+                (row.get("name"), row.get::<String, _>("type").to_uppercase())
+            })
+            .fetch_all(executor)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Columns of the table that stores the submissions of this form with a
+    /// `UNIQUE` constraint of their own.
+    async fn live_unique_columns(&self, tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<String>, Error>{
+        info!("live_unique_columns");
+        let sql = format!("PRAGMA index_list({})", quote(&self.name));
+        let indexes = query(&sql)
+            .map(|row: SqliteRow| -> (String, bool, String) {
+                (row.get("name"), row.get("unique"), row.get("origin"))
+            })
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut columns = Vec::new();
+        for (index, unique, origin) in indexes{
+            if !unique || origin != "u"{
+                continue;
+            }
+            let sql = format!("PRAGMA index_info({})", quote(&index));
+            let names = query(&sql)
+                .map(|row: SqliteRow| -> String {row.get("name")})
+                .fetch_all(&mut *tx)
+                .await?;
+            if names.len() == 1{
+                columns.push(names[0].clone());
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Brings the table that stores the submissions of this form in line with
+    /// its fields. The table is created if it does not exist, new columns are
+    /// added with `ALTER TABLE` and, when a column is dropped, retyped or
+    /// changes its `UNIQUE` constraint, the table is rebuilt copying the
+    /// existing submissions into it. Everything runs in `tx`, so the fields
+    /// saved in it are rolled back with the table when the sync fails.
+    pub async fn sync_table(&self, tx: &mut Transaction<'_, Sqlite>) -> Result<(), Error>{
+        info!("sync_table");
+        // fields in the trash keep their columns until they are purged
+        // without fields the table keeps only the metadata columns
+        let fields = Field::read_all_by_form_id(&mut *tx, self.id).await?;
+        let columns = Self::columns(&fields);
+        let live = self.live_columns(&mut *tx).await?;
+        if live.is_empty(){
+            let sql = format!("CREATE TABLE {} ({});", quote(&self.name),
+                columns.join(", "));
+            debug!("Sql creation query: {}", &sql);
+            query(&sql)
+                .execute(&mut *tx)
+                .await?;
+            return Ok(());
+        }
+        let unique = self.live_unique_columns(tx).await?;
+        let mut added: Vec<&Field> = Vec::new();
+        // tables created before the metadata columns lack all of them
+        let mut rebuild = METADATA.iter()
+            .any(|(meta, _)| !live.iter().any(|(name, _)| name == meta));
+        for field in fields.iter(){
+            match live.iter().find(|(name, _)| name == field.get_name()){
+                Some((name, datatype)) => {
+                    if datatype != field.to_sqlite() ||
+                            unique.contains(name) != field.is_unique(){
+                        rebuild = true;
+                    }
+                },
+                None => {
+                    if field.is_unique(){
+                        rebuild = true;
+                    }
+                    added.push(field);
+                },
+            }
+        }
+        if live.iter().any(|(name, _)| !fields.iter().any(|field| field.get_name() == name)
+                && !METADATA.iter().any(|(meta, _)| meta == name)){
+            rebuild = true;
+        }
+        if rebuild{
+            self.rebuild_table(tx, &fields, &live).await
+        }else{
+            for field in added{
+                let sql = format!("ALTER TABLE {} ADD COLUMN {};",
+                    quote(&self.name), field.to_column());
+                debug!("Sql alter query: {}", &sql);
+                query(&sql)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Column definitions of the table of submissions: the metadata first and
+    /// then the fields.
+    fn columns(fields: &[Field]) -> Vec<String>{
+        METADATA.iter()
+            .map(|(name, definition)| format!("{} {}", quote(name), definition))
+            .chain(fields.iter().map(|field| field.to_column()))
+            .collect()
+    }
+
+    /// Creates a new table from the fields, copies the columns that survive
+    /// and swaps it with the old one, all in the transaction of the sync.
+    async fn rebuild_table(&self, tx: &mut Transaction<'_, Sqlite>, fields: &[Field],
+            live: &[(String, String)]) -> Result<(), Error>{
+        info!("rebuild_table");
+        let temporal = format!("_{}_new", self.name);
+        let columns = Self::columns(fields);
+        let is_live = |column: &str| live.iter().any(|(name, _)| name == column);
+        // the id identifies every submission, old tables keep it as rowid
+        let mut targets = vec![quote("id")];
+        let mut sources = vec![if is_live("id") { quote("id") } else { "rowid".to_string() }];
+        for name in METADATA.iter().map(|(name, _)| *name)
+                .chain(fields.iter().map(|field| field.get_name())){
+            if name != "id" && is_live(name){
+                targets.push(quote(name));
+                sources.push(quote(name));
+            }
+        }
+        let sql = format!("CREATE TABLE {} ({});", quote(&temporal),
+            columns.join(", "));
+        debug!("Sql creation query: {}", &sql);
+        query(&sql).execute(&mut *tx).await?;
+        let sql = format!("INSERT INTO {} ({}) SELECT {} FROM {};",
+            quote(&temporal), targets.join(", "), sources.join(", "),
+            quote(&self.name));
+        debug!("Sql copy query: {}", &sql);
+        query(&sql).execute(&mut *tx).await?;
+        let sql = format!("DROP TABLE {};", quote(&self.name));
+        query(&sql).execute(&mut *tx).await?;
+        let sql = format!("ALTER TABLE {} RENAME TO {};", quote(&temporal),
+            quote(&self.name));
+        query(&sql).execute(&mut *tx).await?;
+        Ok(())
+    }
+
+    /// Finds the unique field whose constraint was violated by a failed
+    /// insert or update, if that is why it failed.
+    pub fn unique_violation<'a>(&self, fields: &'a [Field], error: &Error) -> Option<&'a Field>{
+        let db_error = match error.downcast_ref::<sqlx::Error>(){
+            Some(sqlx::Error::Database(db_error)) => db_error,
+            _ => return None,
+        };
+        // SQLite reports it as "UNIQUE constraint failed: table.column"
+        let message = db_error.message();
+        let columns = message.strip_prefix("UNIQUE constraint failed: ")?;
+        columns.split(", ")
+            .filter_map(|column| column.split_once('.'))
+            .filter(|(table, _)| *table == self.name)
+            .find_map(|(_, column)| fields.iter()
+                .find(|field| field.is_unique() && field.get_name() == column))
+    }
+
+    /// Name of the unique field whose constraint was violated, if that is
+    /// why the insert or update failed, and the message for the user.
+    pub fn duplicated(&self, fields: &[Field], error: &Error) -> Option<(String, String)>{
+        self.unique_violation(fields, error)
+            .map(|field| (field.get_name().to_string(),
+                format!("This {} has already been registered", field.get_label().to_lowercase())))
+    }
+
+    /// Stores a submission. Every field takes its value from `data`, or NULL
+    /// if it is missing or empty, and checkboxes are stored as booleans. The
+    /// submission gets a new uuid, the current time and its origin. Returns
+    /// the id of the submission.
+    pub async fn insert<'c, E>(&self, executor: E, fields: &[Field],
+            data: &HashMap<String, String>, origin: &Origin) -> Result<i64, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        info!("insert");
+        let mut names: Vec<String> = ["uuid", "submitted_at", "ip", "user_agent"].iter()
+            .map(|name| quote(name))
+            .collect();
+        names.extend(fields.iter()
+            .map(|field| quote(field.get_name())));
+        let params: Vec<String> = (1..=names.len())
+            .map(|i| format!("${}", i))
+            .collect();
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", quote(&self.name),
+            names.join(", "), params.join(", "));
+        debug!("Sql insert query: {}", &sql);
+        let mut sql_query = query(&sql)
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(Utc::now())
+            .bind(&origin.ip)
+            .bind(&origin.user_agent);
+        for field in fields{
+            sql_query = Self::bind_field(sql_query, field, data);
+        }
+        Ok(sql_query
+            .execute(executor)
+            .await?
+            .last_insert_rowid())
+    }
+
+    /// Replaces the values of the fields of a submission the same way
+    /// `insert` stores them, keeping its metadata.
+    pub async fn update_row(&self, pool: &SqlitePool, fields: &[Field], id: i64,
+            data: &HashMap<String, String>) -> Result<(), Error>{
+        info!("update_row");
+        if fields.is_empty(){
+            // Nothing to set, the submission only has to exist
+            return self.read_row(pool, id).await.map(|_| ());
+        }
+        let sets: Vec<String> = fields.iter()
+            .enumerate()
+            .map(|(i, field)| format!("{} = ${}", quote(field.get_name()), i + 1))
+            .collect();
+        let sql = format!("UPDATE {} SET {} WHERE id = ${} AND deleted_at IS NULL",
+            quote(&self.name), sets.join(", "), fields.len() + 1);
+        debug!("Sql update query: {}", &sql);
+        let before = self.audited_row(pool, id).await;
+        let mut sql_query = query(&sql);
+        for field in fields{
+            sql_query = Self::bind_field(sql_query, field, data);
+        }
+        let result = sql_query
+            .bind(id)
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        self.record_row(pool, "update", id, before).await;
+        Ok(())
+    }
+
+    /// A submission as JSON for the audit log, in the trash or not.
+    async fn audited_row(&self, pool: &SqlitePool, id: i64) -> Option<serde_json::Value>{
+        let fields = self.get_fields(pool).await.ok()?;
+        let sql = format!("SELECT * FROM {} WHERE id = $1", quote(&self.name));
+        let row = query(&sql)
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .ok()?;
+        Some(serde_json::Value::Object(Self::row_to_json(&fields, &row)))
+    }
+
+    /// Records a change of a submission, reading it as it is after it.
+    async fn record_row(&self, pool: &SqlitePool, action: &str, id: i64,
+            before: Option<serde_json::Value>){
+        let after = self.audited_row(pool, id).await;
+        Audit::record(pool, action, "submission", &format!("{}/{}", self.name, id),
+            before, after).await;
+    }
+
+    /// Binds the value of a field taken from `data`: checkboxes as booleans
+    /// and everything else as text, or NULL when it is missing or blank.
+    fn bind_field<'q>(sql_query: Query<'q, Sqlite, SqliteArguments<'q>>, field: &Field,
+            data: &'q HashMap<String, String>) -> Query<'q, Sqlite, SqliteArguments<'q>>{
+        let value = data.get(field.get_name());
+        if field.get_datatype() == "checkbox"{
+            sql_query.bind(matches!(value.map(|value| value.as_str()),
+                Some("on") | Some("true") | Some("1")))
+        }else{
+            // Empty values are NULL, so they are not duplicates of each other
+            // for unique fields and are left out of ranges
+            match value.filter(|value| !value.trim().is_empty()){
+                Some(value) => sql_query.bind(value),
+                None => sql_query.bind(None::<String>),
+            }
+        }
+    }
+
+    /// Number of submissions of this form that pass the filter.
+    pub async fn count(&self, pool: &SqlitePool, fields: &[Field], filter: &Filter) -> Result<i64, Error>{
+        info!("count");
+        let (where_clause, binds) = filter.where_clause(fields);
+        let sql = format!("SELECT count(*) FROM {} {}", quote(&self.name),
+            where_clause);
+        debug!("Sql count query: {}", &sql);
+        let mut sql_query = query(&sql);
+        for value in binds.iter(){
+            sql_query = sql_query.bind(value);
+        }
+        sql_query
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Submissions of this form that pass the filter, in its order. Without
+    /// `limit` all of them are returned.
+    pub async fn read_rows(&self, pool: &SqlitePool, fields: &[Field], filter: &Filter,
+            limit: Option<i64>, offset: i64) -> Result<Vec<SqliteRow>, Error>{
+        info!("read_rows");
+        let (where_clause, binds) = filter.where_clause(fields);
+        let sql = format!("SELECT * FROM {} {} {} LIMIT ${} OFFSET ${}",
+            quote(&self.name), where_clause, filter.order_clause(),
+            binds.len() + 1, binds.len() + 2);
+        debug!("Sql select query: {}", &sql);
+        let mut sql_query = query(&sql);
+        for value in binds.iter(){
+            sql_query = sql_query.bind(value);
+        }
+        sql_query
+            .bind(limit.unwrap_or(-1))
+            .bind(offset)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn read_row(&self, pool: &SqlitePool, id: i64) -> Result<SqliteRow, Error>{
+        info!("read_row");
+        let sql = format!("SELECT * FROM {} WHERE id = $1 AND deleted_at IS NULL",
+            quote(&self.name));
+        query(&sql)
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Moves a submission to the trash.
+    pub async fn delete_row(&self, pool: &SqlitePool, id: i64) -> Result<(), Error>{
+        info!("delete_row");
+        let sql = format!("UPDATE {} SET deleted_at = $1 WHERE id = $2 AND deleted_at IS NULL",
+            quote(&self.name));
+        let before = self.audited_row(pool, id).await;
+        let result = query(&sql)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        self.record_row(pool, "delete", id, before).await;
+        Ok(())
+    }
+
+    /// Submissions of this form in the trash, the last deleted first.
+    pub async fn read_deleted_rows(&self, pool: &SqlitePool) -> Result<Vec<SqliteRow>, Error>{
+        info!("read_deleted_rows");
+        if self.live_columns(pool).await?.is_empty(){
+            return Ok(Vec::new());
+        }
+        let sql = format!("SELECT * FROM {} WHERE deleted_at IS NOT NULL
+                           ORDER BY deleted_at DESC", quote(&self.name));
+        query(&sql)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Takes a submission out of the trash.
+    pub async fn restore_row(&self, pool: &SqlitePool, id: i64) -> Result<(), Error>{
+        info!("restore_row");
+        let sql = format!("UPDATE {} SET deleted_at = NULL WHERE id = $1
+                           AND deleted_at IS NOT NULL", quote(&self.name));
+        let result = query(&sql)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        self.record_row(pool, "restore", id, None).await;
+        Ok(())
+    }
+
+    /// Deletes the uploads of the `file` fields, in the trash or not, of the
+    /// submissions that `condition` picks with `value` as `$2`, before they
+    /// are deleted for good.
+    async fn purge_uploads<T>(&self, tx: &mut Transaction<'_, Sqlite>, condition: &str,
+            value: T) -> Result<(), Error>
+            where T: for<'q> Encode<'q, Sqlite> + Type<Sqlite> + Send + Clone + 'static{
+        info!("purge_uploads");
+        let fields = Field::read_all_by_form_id(&mut *tx, self.id).await?;
+        for field in fields.iter().filter(|field| field.get_datatype() == "file"){
+            let sql = format!("DELETE FROM uploads WHERE form_id = $1 AND id IN
+                               (SELECT CAST({} AS INTEGER) FROM {} WHERE {})",
+                quote(field.get_name()), quote(&self.name), condition);
+            query(&sql)
+                .bind(self.id)
+                .bind(value.clone())
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes for good a submission in the trash, with its uploads.
+    pub async fn purge_row(&self, pool: &SqlitePool, id: i64) -> Result<(), Error>{
+        info!("purge_row");
+        let sql = format!("DELETE FROM {} WHERE id = $1 AND deleted_at IS NOT NULL",
+            quote(&self.name));
+        let before = self.audited_row(pool, id).await;
+        let mut tx = pool.begin().await?;
+        self.purge_uploads(&mut tx, "id = $2 AND deleted_at IS NOT NULL", id).await?;
+        let result = query(&sql)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        tx.commit().await?;
+        self.record_row(pool, "purge", id, before).await;
+        Ok(())
+    }
+
+    /// Deletes for good the submissions moved to the trash before `before`,
+    /// with their uploads, and records each of them in the audit log.
+    pub async fn purge_rows(&self, pool: &SqlitePool, before: DateTime<Utc>) -> Result<u64, Error>{
+        info!("purge_rows");
+        if self.live_columns(pool).await?.is_empty(){
+            return Ok(0);
+        }
+        let fields = self.get_fields(pool).await?;
+        let mut tx = pool.begin().await?;
+        self.purge_uploads(&mut tx, "deleted_at < $2", before).await?;
+        let sql = format!("DELETE FROM {} WHERE deleted_at < $1 RETURNING *",
+            quote(&self.name));
+        let purged = query(&sql)
+            .bind(before)
+            .fetch_all(&mut tx)
+            .await?;
+        tx.commit().await?;
+        for row in purged.iter(){
+            let id: i64 = row.get("id");
+            Audit::record(pool, "purge", "submission", &format!("{}/{}", self.name, id),
+                Some(serde_json::Value::Object(Self::row_to_json(&fields, row))), None).await;
+        }
+        Ok(purged.len() as u64)
+    }
+
+    /// A submission as a JSON object, with its metadata and the value of
+    /// every field.
+    pub fn row_to_json(fields: &[Field], row: &SqliteRow) -> serde_json::Map<String, serde_json::Value>{
+        let mut map = serde_json::Map::new();
+        map.insert("id".to_string(), row.try_get::<i64, _>("id")
+            .map(serde_json::Value::from)
+            .unwrap_or(serde_json::Value::Null));
+        for name in ["uuid", "submitted_at", "ip", "user_agent", "deleted_at"]{
+            let value = row.try_get::<Option<String>, _>(name)
+                .ok()
+                .flatten()
+                .map(serde_json::Value::from)
+                .unwrap_or(serde_json::Value::Null);
+            map.insert(name.to_string(), value);
+        }
+        for field in fields{
+            let name = field.get_name();
+            let value = if let Ok(Some(value)) = row.try_get::<Option<String>, _>(name){
+                if field.get_datatype() == "checkboxes"{
+                    serde_json::Value::from(Field::chosen(&value))
+                }else{
+                    serde_json::Value::from(value)
+                }
+            }else if let Ok(Some(value)) = row.try_get::<Option<i64>, _>(name){
+                serde_json::Value::from(value)
+            }else if let Ok(Some(value)) = row.try_get::<Option<f64>, _>(name){
+                serde_json::Value::from(value)
+            }else{
+                serde_json::Value::Null
+            };
+            map.insert(name.to_string(), value);
+        }
+        map
+    }
 }
 
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::NewField;
+
+    /// A `contact` form with a `name` text field and one submission.
+    async fn contact(pool: &SqlitePool) -> (Form, Vec<Field>, i64){
+        let (form, fields) = crate::migrations::test_form(pool, "contact", vec![
+            NewField{label: "Name".to_string(), ..crate::migrations::test_field("name", "text")},
+        ]).await;
+        let data = HashMap::from([("name".to_string(), "Ana".to_string())]);
+        let id = form.insert(pool, &fields, &data, &Default::default()).await.unwrap();
+        (form, fields, id)
+    }
+
+    #[actix_web::test]
+    async fn update_row_without_fields_checks_the_row(){
+        let pool = crate::migrations::test_pool().await;
+        let (form, _, id) = contact(&pool).await;
+        assert!(form.update_row(&pool, &[], id, &HashMap::new()).await.is_ok());
+        assert!(form.update_row(&pool, &[], id + 1, &HashMap::new()).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn purge_rows_records_each_submission(){
+        use crate::models::AuditFilter;
+        let pool = crate::migrations::test_pool().await;
+        let (form, _, id) = contact(&pool).await;
+        form.delete_row(&pool, id).await.unwrap();
+        assert_eq!(form.purge_rows(&pool, Utc::now()).await.unwrap(), 1);
+        let filter = AuditFilter{
+            action: Some("purge".to_string()),
+            entity: Some("submission".to_string()),
+            ..Default::default()
+        };
+        let purged = Audit::read_page(&pool, &filter, None, 0).await.unwrap();
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].entity_id, format!("contact/{}", id));
+        assert_eq!(purged[0].actor, "system");
+        assert!(purged[0].before.as_deref().unwrap().contains("Ana"));
+    }
+}
@@ -1,15 +1,37 @@
+pub mod audit;
+mod challenge;
 mod field;
+mod filter;
 mod form;
+mod notification;
 mod param;
+mod protection;
 mod user;
 mod role;
+mod token;
+mod trash;
+mod upload;
 mod utils;
+mod validation;
+mod webhook;
 
 pub use param::Param;
-pub use field::Field;
-pub use form::Form;
-pub use user::User;
+pub use protection::Protection;
+pub use audit::{Audit, AuditFilter};
+pub use challenge::Challenge;
+pub use field::{Field, NewField, Choice, Constraints};
+pub use validation::{validate, validate_field, validate_definition, is_valid_field_name,
+    is_valid_form_name, DATATYPES};
+pub use form::{Form, Origin, METADATA};
+pub use notification::{Notification, DEFAULT_SUBJECT};
+pub use filter::{Filter, FilterKind};
+pub use user::{User, TokenClaims, UserSchema, FilteredUser};
 pub use role::Role;
+pub use token::Token;
+pub use trash::Trash;
+pub use upload::Upload;
+pub use webhook::{Webhook, Delivery};
+pub use utils::{quote, default_template, default_datatype};
 use utils::default_datetime;
 
 pub type Error = Box<dyn std::error::Error>;
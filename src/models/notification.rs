@@ -0,0 +1,100 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::{Audit, Error, audit::to_json};
+
+/// Subject of the emails when the form has none.
+pub const DEFAULT_SUBJECT: &str = "New submission of {{ title }}";
+
+/// Who is told by email of the new submissions of a form.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notification{
+    pub form_id: i64,
+    pub enabled: bool,
+    /// Email addresses separated by commas.
+    pub recipients: String,
+    /// Tera template with `name`, `title`, `id` and the answers as `values`.
+    pub subject: String,
+    pub include_answers: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Notification{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            form_id: row.get("form_id"),
+            enabled: row.get("enabled"),
+            recipients: row.get("recipients"),
+            subject: row.get("subject"),
+            include_answers: row.get("include_answers"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    /// The settings of a form, disabled when they were never saved.
+    pub async fn read(pool: &SqlitePool, form_id: i64) -> Result<Self, Error>{
+        info!("read");
+        let sql = "SELECT * FROM notifications WHERE form_id = $1";
+        let notification = query(sql)
+            .bind(form_id)
+            .map(Self::from_row)
+            .fetch_optional(pool)
+            .await?;
+        Ok(notification.unwrap_or_else(|| Self{
+            form_id,
+            enabled: false,
+            recipients: "".to_string(),
+            subject: DEFAULT_SUBJECT.to_string(),
+            include_answers: true,
+            updated_at: Utc::now(),
+        }))
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Result<Self, Error>{
+        info!("save");
+        let before = Self::read(pool, self.form_id).await.ok();
+        let sql = "INSERT INTO notifications (form_id, enabled, recipients, subject,
+                   include_answers, updated_at) VALUES ($1, $2, $3, $4, $5, $6)
+                   ON CONFLICT(form_id) DO UPDATE SET
+                   enabled = excluded.enabled,
+                   recipients = excluded.recipients,
+                   subject = excluded.subject,
+                   include_answers = excluded.include_answers,
+                   updated_at = excluded.updated_at
+                   RETURNING *";
+        let notification = query(sql)
+            .bind(self.form_id)
+            .bind(self.enabled)
+            .bind(&self.recipients)
+            .bind(&self.subject)
+            .bind(self.include_answers)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "update", "notification", &self.form_id.to_string(),
+            before.as_ref().and_then(to_json), to_json(&notification)).await;
+        Ok(notification)
+    }
+
+    /// Addresses of the recipients, without the empty ones.
+    pub fn get_recipients(&self) -> Vec<String>{
+        self.recipients.split(',')
+            .map(|recipient| recipient.trim().to_string())
+            .filter(|recipient| !recipient.is_empty())
+            .collect()
+    }
+}
@@ -1,11 +1,35 @@
 use serde::{Serialize, Deserialize};
 use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
 use chrono::{DateTime, Utc};
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
 use std::collections::HashMap;
+use serde_json::Value;
 
 // Here my things
-use super::Error;
+use super::{Audit, Error};
+
+/// Parameters whose values are never written to the audit log nor to the
+/// debug log.
+const SECRETS: [&str; 6] = ["salt", "pepper", "jwt_secret", "session_key",
+    "smtp_password", "form_secret"];
+
+/// Seconds the API tokens last when `jwt_expires_in` is wrong.
+const DEFAULT_JWT_EXPIRES_IN: i64 = 3600;
+
+/// Days in the trash when `trash_retention_days` is wrong.
+const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+/// Tries of a webhook delivery when `webhook_max_attempts` is wrong.
+const DEFAULT_WEBHOOK_MAX_ATTEMPTS: i64 = 8;
+
+/// The value as it can be logged, masked if the parameter is a secret.
+fn shown<'a>(key: &str, value: &'a str) -> &'a str{
+    if SECRETS.contains(&key){
+        "***"
+    }else{
+        value
+    }
+}
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,16 +48,19 @@ impl Param{
         self.id
     }
 
+    #[allow(dead_code)]
     pub fn get_key(&self) -> &str{
         &self.key
     }
 
+    #[allow(dead_code)]
     pub async fn get_url(pool: &SqlitePool) -> String{
         Self::get(pool, "url")
             .await
             .unwrap()
     }
 
+    #[allow(dead_code)]
     pub async fn get_port(pool: &SqlitePool) -> u16{
         Self::get(pool, "port")
             .await
@@ -48,6 +75,63 @@ impl Param{
             .unwrap()
     }
 
+    /// Seconds the API tokens last, 3600 if the parameter is missing or
+    /// not a number.
+    pub async fn get_jwt_expires_in(pool: &SqlitePool) -> i64{
+        let value = Self::get(pool, "jwt_expires_in").await;
+        match value.as_deref().map(str::parse::<i64>){
+            Ok(Ok(expires_in)) => expires_in,
+            _ => {
+                warn!("Wrong jwt_expires_in {:?}, using {}", value.ok(),
+                    DEFAULT_JWT_EXPIRES_IN);
+                DEFAULT_JWT_EXPIRES_IN
+            },
+        }
+    }
+
+    /// Days that deleted forms, fields, users and submissions stay in the
+    /// trash before they are purged, 30 if the parameter is missing or not
+    /// a number.
+    pub async fn get_trash_retention_days(pool: &SqlitePool) -> i64{
+        let value = Self::get(pool, "trash_retention_days").await;
+        match value.as_deref().map(str::parse::<i64>){
+            Ok(Ok(days)) => days,
+            _ => {
+                warn!("Wrong trash_retention_days {:?}, using {}", value.ok(),
+                    DEFAULT_TRASH_RETENTION_DAYS);
+                DEFAULT_TRASH_RETENTION_DAYS
+            },
+        }
+    }
+
+    /// Times a webhook delivery is tried before it is given up, 8 if the
+    /// parameter is missing or not a number.
+    pub async fn get_webhook_max_attempts(pool: &SqlitePool) -> i64{
+        let value = Self::get(pool, "webhook_max_attempts").await;
+        match value.as_deref().map(str::parse::<i64>){
+            Ok(Ok(attempts)) => attempts,
+            _ => {
+                warn!("Wrong webhook_max_attempts {:?}, using {}", value.ok(),
+                    DEFAULT_WEBHOOK_MAX_ATTEMPTS);
+                DEFAULT_WEBHOOK_MAX_ATTEMPTS
+            },
+        }
+    }
+
+    /// Key of the signature of the tokens of the public forms.
+    pub async fn get_form_secret(pool: &SqlitePool) -> String{
+        Self::get(pool, "form_secret")
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_session_key(pool: &SqlitePool) -> String{
+        Self::get(pool, "session_key")
+            .await
+            .unwrap()
+    }
+
+    #[allow(dead_code)]
     pub async fn get_sleep_time(pool: &SqlitePool) -> u64{
         Self::get(pool, "sleep_time")
             .await
@@ -56,6 +140,7 @@ impl Param{
             .unwrap()
     }
 
+    #[allow(dead_code)]
     pub async fn get_older_than(pool: &SqlitePool) -> i32{
         Self::get(pool, "older_than")
             .await
@@ -64,14 +149,17 @@ impl Param{
             .unwrap()
     }
 
+    #[allow(dead_code)]
     pub fn get_value(&self) -> &str{
         &self.value
     }
 
+    #[allow(dead_code)]
     pub fn get_created_at(&self) -> &DateTime<Utc>{
         &self.created_at
     }
 
+    #[allow(dead_code)]
     pub fn get_updated_at(&self) -> &DateTime<Utc>{
         &self.updated_at
     }
@@ -106,13 +194,14 @@ impl Param{
             .await?;
         let mut kv = HashMap::new();
         for param in params{
-            debug!("{:?}", param);
+            debug!("{}={}", param.key, shown(&param.key, &param.value));
             kv.insert(param.key, param.value);
         }
         Ok(kv)
     }
 
 
+    #[allow(dead_code)]
     pub async fn exists(pool: &SqlitePool, key: &str) -> Result<bool, Error>{
         debug!("exists {key}");
         let sql = "SELECT count(key) FROM config WHERE key = $1";
@@ -124,7 +213,7 @@ impl Param{
     }
 
     pub async fn set(pool: &SqlitePool, key: &str, value: &str) -> Result<Param, Error>{
-        debug!("set {key}={value}");
+        debug!("set {key}={}", shown(key, value));
         let current_ts = Utc::now();
         let sql = "INSERT INTO config(key, value, updated_at) \
             VALUES($1, $2, $3)
@@ -132,14 +221,24 @@ impl Param{
             value=excluded.value,
             updated_at=excluded.updated_at
             RETURNING *";
-        query(sql)
+        let before = Self::get(pool, key).await.ok();
+        let param = query(sql)
             .bind(key)
             .bind(value)
             .bind(current_ts)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-            .map_err(|e| e.into())
+            .await?;
+        let audited = |value: Option<String>| value.map(|value|{
+            if SECRETS.contains(&key){
+                Value::from("********")
+            }else{
+                Value::from(value)
+            }
+        });
+        Audit::record(pool, "update", "param", key, audited(before),
+            audited(Some(param.value.clone()))).await;
+        Ok(param)
     }
 }
 
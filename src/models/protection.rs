@@ -0,0 +1,105 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::{Audit, Error, audit::to_json};
+
+/// How a public form is protected against bots.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Protection{
+    pub form_id: i64,
+    /// Adds a hidden field, named `honeypot_name`, that only bots fill.
+    pub honeypot: bool,
+    pub honeypot_name: String,
+    /// Submissions made sooner after the form was shown are rejected. Zero
+    /// disables the check.
+    pub min_fill_seconds: i64,
+    /// Submissions allowed from the same address every `rate_window`
+    /// seconds. Zero disables the limit.
+    pub rate_limit: i64,
+    pub rate_window: i64,
+    /// Leading zero bits of the proof of work asked to the browser. Zero
+    /// disables it.
+    pub pow_difficulty: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Protection{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            form_id: row.get("form_id"),
+            honeypot: row.get("honeypot"),
+            honeypot_name: row.get("honeypot_name"),
+            min_fill_seconds: row.get("min_fill_seconds"),
+            rate_limit: row.get("rate_limit"),
+            rate_window: row.get("rate_window"),
+            pow_difficulty: row.get("pow_difficulty"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    /// The protection of a form, the default one when it was never saved.
+    pub async fn read(pool: &SqlitePool, form_id: i64) -> Result<Self, Error>{
+        info!("read");
+        let sql = "SELECT * FROM protections WHERE form_id = $1";
+        let protection = query(sql)
+            .bind(form_id)
+            .map(Self::from_row)
+            .fetch_optional(pool)
+            .await?;
+        Ok(protection.unwrap_or_else(|| Self{
+            form_id,
+            honeypot: true,
+            honeypot_name: "website".to_string(),
+            min_fill_seconds: 0,
+            rate_limit: 10,
+            rate_window: 60,
+            pow_difficulty: 0,
+            updated_at: Utc::now(),
+        }))
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Result<Self, Error>{
+        info!("save");
+        let before = Self::read(pool, self.form_id).await.ok();
+        let sql = "INSERT INTO protections (form_id, honeypot, honeypot_name,
+                   min_fill_seconds, rate_limit, rate_window, pow_difficulty,
+                   updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                   ON CONFLICT(form_id) DO UPDATE SET
+                   honeypot = excluded.honeypot,
+                   honeypot_name = excluded.honeypot_name,
+                   min_fill_seconds = excluded.min_fill_seconds,
+                   rate_limit = excluded.rate_limit,
+                   rate_window = excluded.rate_window,
+                   pow_difficulty = excluded.pow_difficulty,
+                   updated_at = excluded.updated_at
+                   RETURNING *";
+        let protection = query(sql)
+            .bind(self.form_id)
+            .bind(self.honeypot)
+            .bind(&self.honeypot_name)
+            .bind(self.min_fill_seconds)
+            .bind(self.rate_limit)
+            .bind(self.rate_window)
+            .bind(self.pow_difficulty)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "update", "protection", &self.form_id.to_string(),
+            before.as_ref().and_then(to_json), to_json(&protection)).await;
+        Ok(protection)
+    }
+}
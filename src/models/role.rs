@@ -1,7 +1,7 @@
 use serde::{Serialize, Deserialize};
 
 #[derive(sqlx::Type)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Role {
     User,
     Admin,
@@ -0,0 +1,54 @@
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::Error;
+
+/// Revocation list of the bearer tokens. A token stays here until it
+/// expires, when it would be rejected anyway.
+pub struct Token;
+
+impl Token{
+    pub async fn revoke(pool: &SqlitePool, jti: &str, expires_at: DateTime<Utc>) -> Result<(), Error>{
+        info!("revoke");
+        let sql = "INSERT OR IGNORE INTO revoked_tokens (jti, expires_at)
+                   VALUES ($1, $2)";
+        query(sql)
+            .bind(jti)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn is_revoked(pool: &SqlitePool, jti: &str) -> Result<bool, Error>{
+        info!("is_revoked");
+        let sql = "SELECT count(jti) FROM revoked_tokens WHERE jti = $1";
+        Ok(query(sql)
+            .bind(jti)
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await? > 0)
+    }
+
+    pub async fn purge_expired(pool: &SqlitePool) -> Result<u64, Error>{
+        info!("purge_expired");
+        let sql = "DELETE FROM revoked_tokens WHERE expires_at < $1";
+        Ok(query(sql)
+            .bind(Utc::now())
+            .execute(pool)
+            .await?
+            .rows_affected())
+    }
+}
@@ -0,0 +1,49 @@
+use sqlx::sqlite::SqlitePool;
+use chrono::{
+    Duration,
+    Utc
+};
+use tracing::{info, debug};
+
+// my own uses
+use super::{
+    Error,
+    Field,
+    Form,
+    Param,
+    User,
+};
+
+/// Forms, fields, users and submissions deleted are kept in the trash for
+/// `trash_retention_days` before they are purged.
+pub struct Trash;
+
+impl Trash{
+    /// Purges everything that has been in the trash longer than the
+    /// retention period. Returns how many items were purged.
+    pub async fn purge_expired(pool: &SqlitePool) -> Result<u64, Error>{
+        info!("purge_expired");
+        let days = Param::get_trash_retention_days(pool).await;
+        let before = Utc::now() - Duration::days(days);
+        let mut purged = 0;
+        for form in Form::read_deleted(pool).await?{
+            if form.deleted_at.map(|deleted_at| deleted_at < before).unwrap_or(false){
+                debug!("Purging form {}", form.name);
+                Form::purge(pool, form.id).await?;
+                purged += 1;
+            }
+        }
+        for field in Field::read_deleted(pool).await?{
+            if field.get_deleted_at().map(|deleted_at| deleted_at < before).unwrap_or(false){
+                debug!("Purging field {}", field.get_name());
+                Field::purge(pool, field.get_id()).await?;
+                purged += 1;
+            }
+        }
+        purged += User::purge_deleted(pool, before).await?;
+        for form in Form::read_all(pool).await?{
+            purged += form.purge_rows(pool, before).await?;
+        }
+        Ok(purged)
+    }
+}
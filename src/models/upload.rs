@@ -0,0 +1,98 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::Error;
+
+/// A file sent with a `file` field. Its content is `data`, or the file named
+/// after `sha256` in the upload directory when `data` is `None`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Upload{
+    pub id: i64,
+    pub form_id: i64,
+    pub sha256: String,
+    pub name: String,
+    pub mime: String,
+    pub size: i64,
+    #[serde(skip)]
+    pub data: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Upload{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            form_id: row.get("form_id"),
+            sha256: row.get("sha256"),
+            name: row.get("name"),
+            mime: row.get("mime"),
+            size: row.get("size"),
+            data: row.get("data"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    pub async fn create(pool: &SqlitePool, form_id: i64, sha256: &str, name: &str,
+            mime: &str, size: i64, data: Option<&[u8]>) -> Result<Self, Error>{
+        info!("create");
+        let sql = "INSERT INTO uploads (form_id, sha256, name, mime, size, data,
+                   created_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *";
+        query(sql)
+            .bind(form_id)
+            .bind(sha256)
+            .bind(name)
+            .bind(mime)
+            .bind(size)
+            .bind(data)
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("read");
+        let sql = "SELECT * FROM uploads WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<(), Error>{
+        info!("delete");
+        let sql = "DELETE FROM uploads WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Checks if any upload is kept in the file named after `sha256`.
+    pub async fn is_on_disk(pool: &SqlitePool, sha256: &str) -> Result<bool, Error>{
+        info!("is_on_disk");
+        let sql = "SELECT count(id) FROM uploads WHERE sha256 = $1 AND data IS NULL";
+        Ok(query(sql)
+            .bind(sha256)
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await? > 0)
+    }
+}
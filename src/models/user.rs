@@ -8,8 +8,19 @@ use sqlx::{
     query,
     Row,
 };
-use tracing::info;
+use tracing::{info, debug};
+use argon2::{
+    password_hash::{
+        rand_core::OsRng,
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString
+    },
+    Argon2
+};
 use super::{
+    Audit,
     Error,
     Role,
     default_datetime, Param,
@@ -26,13 +37,16 @@ pub struct User{
     created_at: DateTime<Utc>,
     #[serde(default = "default_datetime")]
     updated_at: DateTime<Utc>,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TokenClaims {
     pub sub: String,
     pub iat: usize,
     pub exp: usize,
+    pub jti: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,14 +65,120 @@ pub struct FilteredUser {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Legacy hash, an md5 of the password between the global `salt` and
+/// `pepper` params. Only used to check passwords stored before Argon2id.
 fn wrap(salt: &str, pepper: &str, word: &str) -> String{
     info!("wrap");
     let composition = format!("{}{}{}", salt, word, pepper);
     format!("{:x}", md5::compute(composition))
 }
 
+/// Hashes a password with Argon2id and a random salt of its own, as a PHC
+/// string. Argon2 is slow on purpose, so it runs apart from the workers.
+async fn hash(password: &str) -> Result<String, Error>{
+    info!("hash");
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hashed| hashed.to_string())
+            .map_err(|e| e.to_string())
+    })
+    .await?
+    .map_err(|e| e.into())
+}
+
+/// Argon2id hash with the default params of no password of any user. It is
+/// checked when there is no user to log in, so that the answer takes as long
+/// as with a name that exists.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$L9tMXcBpV1C3W8p5DZmWTg$zAQQH2w0SUkX6BzECKJuQJQpJL6QoL4eVoQpGeuowYg";
+
+/// Checks a password against its Argon2id hash apart from the workers.
+async fn verify(hashed_password: &str, password: &str) -> Result<bool, Error>{
+    let hashed_password = hashed_password.to_string();
+    let password = password.to_string();
+    let valid = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hashed_password)
+            .map(|parsed| Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok())
+            .map_err(|e| e.to_string())
+    })
+    .await??;
+    Ok(valid)
+}
+
+fn is_legacy(hashed_password: &str) -> bool{
+    !hashed_password.starts_with('$')
+}
+
+
+impl From<&User> for FilteredUser{
+    fn from(user: &User) -> Self{
+        Self{
+            id: user.id,
+            name: user.name.clone(),
+            role: format!("{:?}", user.role),
+            verified: user.active,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// A user as JSON for the audit log, without its password hash.
+fn audited(user: &User) -> Option<serde_json::Value>{
+    let mut value = serde_json::to_value(user).ok()?;
+    if let Some(object) = value.as_object_mut(){
+        object.remove("hashed_password");
+    }
+    Some(value)
+}
 
 impl User{
+    pub fn get_id(&self) -> i64{
+        self.id
+    }
+
+    pub fn is_admin(&self) -> bool{
+        self.active && self.role == Role::Admin
+    }
+
+    /// Checks the password of the user. A valid password stored with the
+    /// legacy md5 hash is stored again with Argon2id.
+    pub async fn check_password(&mut self, pool: &SqlitePool, password: &str) -> Result<bool, Error>{
+        info!("check_password");
+        if is_legacy(&self.hashed_password){
+            let salt = Param::get(pool, "salt").await?;
+            let pepper = Param::get(pool, "pepper").await?;
+            if wrap(&salt, &pepper, password) != self.hashed_password{
+                return Ok(false);
+            }
+            debug!("Upgrading password hash of {}", self.name);
+            self.set_password(pool, password.to_string()).await?;
+            return Ok(true);
+        }
+        verify(&self.hashed_password, password).await
+    }
+
+    /// The active user with this name, if the password is right. Without
+    /// such a user the password is still checked against a dummy hash, so the
+    /// time it takes does not tell which names exist.
+    pub async fn login(pool: &SqlitePool, name: &str, password: &str) -> Result<Option<User>, Error>{
+        info!("login");
+        match Self::get_by_name(pool, name).await{
+            Ok(mut user) if user.active => {
+                let valid = user.check_password(pool, password).await?;
+                Ok(if valid { Some(user) } else { None })
+            },
+            _ => {
+                verify(DUMMY_HASH, password).await?;
+                Ok(None)
+            },
+        }
+    }
+
     fn from_row(row: SqliteRow) -> Self{
         info!("from_row");
         Self{
@@ -69,25 +189,21 @@ impl User{
             active: row.get("active"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            deleted_at: row.get("deleted_at"),
         }
     }
 
     pub async fn set_password(&mut self, pool: &SqlitePool, password: String) -> Result<Self, Error>{
         info!("set_password");
-        let salt = Param::get(pool, "salt").await?;
-        let pepper = Param::get(pool, "pepper").await?;
-        let hashed_password = wrap(&salt, &pepper, &password);
-        self.hashed_password = hashed_password;
+        self.hashed_password = hash(&password).await?;
         self.save(pool).await
     }
 
     pub async fn new(pool: &SqlitePool, name: String, password: String, role: Role, active: bool) -> Result<Self, Error>{
         info!("new");
-        let salt = Param::get(pool, "salt").await?;
-        let pepper = Param::get(pool, "pepper").await?;
-        let hashed_password = wrap(&salt, &pepper, &password);
+        let hashed_password = hash(&password).await?;
         let created_at = Utc::now();
-        let updated_at = created_at.clone();
+        let updated_at = created_at;
         let mut user = Self{
             id: -1,
             name,
@@ -96,8 +212,9 @@ impl User{
             active,
             created_at,
             updated_at,
+            deleted_at: None,
         };
-        user.save(&pool).await
+        user.save(pool).await
     }
 
     pub async fn save(&mut self, pool: &SqlitePool) -> Result<Self, Error>{
@@ -113,6 +230,7 @@ impl User{
         }
     }
 
+    #[allow(dead_code)]
     pub async fn delete(&mut self, pool: &SqlitePool) -> Result<Self, Error>{
         info!("remove");
         Self::remove(pool, self.id).await
@@ -122,51 +240,145 @@ impl User{
         info!("create");
         let sql = "INSERT INTO users (name, hashed_password, role, active, created_at,
                    updated_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *";
-        query(sql)
+        let created = query(sql)
             .bind(&user.name)
             .bind(&user.hashed_password)
             .bind(&user.role)
-            .bind(&user.active)
-            .bind(&user.created_at)
-            .bind(&user.updated_at)
+            .bind(user.active)
+            .bind(user.created_at)
+            .bind(user.updated_at)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-            .map_err(|e| e.into())
+            .await?;
+        Audit::record(pool, "create", "user", &created.id.to_string(),
+            None, audited(&created)).await;
+        Ok(created)
     }
 
+    /// Moves the user to the trash, where it can not log in.
+    #[allow(dead_code)]
     pub async fn remove(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
         info!("delete");
-        let sql = "DELETE FROM users WHERE id = $1 RETURNING *";
-        query(sql)
+        let sql = "UPDATE users SET deleted_at = $1 WHERE id = $2
+                   AND deleted_at IS NULL RETURNING *";
+        let removed = query(sql)
+            .bind(Utc::now())
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "delete", "user", &removed.id.to_string(),
+            None, audited(&removed)).await;
+        Ok(removed)
+    }
+
+    /// Takes the user out of the trash.
+    pub async fn restore(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("restore");
+        let sql = "UPDATE users SET deleted_at = NULL WHERE id = $1
+                   AND deleted_at IS NOT NULL RETURNING *";
+        let restored = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "restore", "user", &restored.id.to_string(),
+            None, audited(&restored)).await;
+        Ok(restored)
+    }
+
+    /// Deletes for good a user in the trash.
+    pub async fn purge(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("purge");
+        let sql = "DELETE FROM users WHERE id = $1 AND deleted_at IS NOT NULL
+                   RETURNING *";
+        let purged = query(sql)
             .bind(id)
             .map(Self::from_row)
             .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "purge", "user", &purged.id.to_string(),
+            audited(&purged), None).await;
+        Ok(purged)
+    }
+
+    /// Deletes for good the users moved to the trash before `before`, and
+    /// records each of them in the audit log.
+    pub async fn purge_deleted(pool: &SqlitePool, before: DateTime<Utc>) -> Result<u64, Error>{
+        info!("purge_deleted");
+        let sql = "DELETE FROM users WHERE deleted_at < $1 RETURNING *";
+        let purged = query(sql)
+            .bind(before)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await?;
+        for user in purged.iter(){
+            Audit::record(pool, "purge", "user", &user.id.to_string(),
+                audited(user), None).await;
+        }
+        Ok(purged.len() as u64)
+    }
+
+    /// Users in the trash, the last deleted first.
+    pub async fn read_deleted(pool: &SqlitePool) -> Result<Vec<User>, Error>{
+        info!("read_deleted");
+        let sql = "SELECT * FROM users WHERE deleted_at IS NOT NULL
+                   ORDER BY deleted_at DESC";
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool)
             .await
-        .map_err(|e| e.into())
+            .map_err(|e| e.into())
     }
 
     pub async fn update(pool: &SqlitePool, user: &Self) -> Result<Self, Error>{
         info!("update");
+        let before = Self::read(pool, user.id).await?;
         let updated_at = Utc::now();
         let sql = "UPDATE users SET hashed_password = $1, role = $2,
                    active = $3, updated_at = $4 WHERE id = $5 RETURNING *";
-        query(sql)
+        let updated = query(sql)
             .bind(&user.hashed_password)
             .bind(&user.role)
-            .bind(&user.active)
+            .bind(user.active)
             .bind(updated_at)
-            .bind(&user.id)
+            .bind(user.id)
             .map(Self::from_row)
             .fetch_one(pool)
-            .await
-        .map_err(|e| e.into())
+            .await?;
+        Audit::record(pool, "update", "user", &updated.id.to_string(),
+            audited(&before), audited(&updated)).await;
+        Ok(updated)
     }
 
 
 
+    pub async fn read(pool: &SqlitePool, id: i64) -> Result<User, Error>{
+        info!("read");
+        let sql = "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Checks if any active administrator is out of the trash.
+    pub async fn has_active_admin(pool: &SqlitePool) -> Result<bool, Error>{
+        info!("has_active_admin");
+        let sql = "SELECT count(id) FROM users WHERE role = $1 AND active
+                   AND deleted_at IS NULL";
+        Ok(query(sql)
+            .bind(Role::Admin)
+            .map(|row: SqliteRow| -> i64 {row.get(0)})
+            .fetch_one(pool)
+            .await? > 0)
+    }
+
     pub async fn get_by_name(pool: &SqlitePool, name: &str) -> Result<User, Error>{
-        let sql = "SELECT * FROM users WHERE name = $1";
+        info!("get_by_name");
+        let sql = "SELECT * FROM users WHERE name = $1 AND deleted_at IS NULL";
         query(sql)
             .bind(name)
             .map(Self::from_row)
@@ -176,3 +388,76 @@ impl User{
     }
 }
 
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// An active user whose password is stored with the legacy md5 hash.
+    async fn legacy_user(pool: &SqlitePool, password: &str) -> User{
+        let mut user = User::new(pool, "admin".to_string(), "other".to_string(),
+            Role::Admin, true).await.unwrap();
+        let salt = Param::get(pool, "salt").await.unwrap();
+        let pepper = Param::get(pool, "pepper").await.unwrap();
+        user.hashed_password = wrap(&salt, &pepper, password);
+        user.save(pool).await.unwrap();
+        user
+    }
+
+    #[actix_web::test]
+    async fn login_upgrades_a_legacy_hash(){
+        let pool = crate::migrations::test_pool().await;
+        let user = legacy_user(&pool, "secret").await;
+        let logged = User::login(&pool, "admin", "secret").await.unwrap();
+        assert_eq!(logged.map(|logged| logged.id), Some(user.id));
+        let stored = User::read(&pool, user.id).await.unwrap();
+        assert!(stored.hashed_password.starts_with("$argon2id$"));
+        assert!(PasswordHash::new(&stored.hashed_password).is_ok());
+        // The new hash takes the same password
+        assert!(User::login(&pool, "admin", "secret").await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn has_active_admin_skips_the_trash_and_inactive_users(){
+        let pool = crate::migrations::test_pool().await;
+        assert!(!User::has_active_admin(&pool).await.unwrap());
+        User::new(&pool, "user".to_string(), "secret".to_string(),
+            Role::User, true).await.unwrap();
+        User::new(&pool, "inactive".to_string(), "secret".to_string(),
+            Role::Admin, false).await.unwrap();
+        let admin = User::new(&pool, "admin".to_string(), "secret".to_string(),
+            Role::Admin, true).await.unwrap();
+        assert!(User::has_active_admin(&pool).await.unwrap());
+        User::remove(&pool, admin.id).await.unwrap();
+        assert!(!User::has_active_admin(&pool).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn purge_deleted_records_each_user_as_the_system(){
+        use crate::models::AuditFilter;
+        let pool = crate::migrations::test_pool().await;
+        for name in ["ana", "bea"]{
+            let user = User::new(&pool, name.to_string(), "secret".to_string(),
+                Role::User, true).await.unwrap();
+            User::remove(&pool, user.id).await.unwrap();
+        }
+        assert_eq!(User::purge_deleted(&pool, Utc::now()).await.unwrap(), 2);
+        let filter = AuditFilter{
+            actor: Some("system".to_string()),
+            action: Some("purge".to_string()),
+            entity: Some("user".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Audit::count(&pool, &filter).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn login_keeps_a_legacy_hash_with_a_wrong_password(){
+        let pool = crate::migrations::test_pool().await;
+        let user = legacy_user(&pool, "secret").await;
+        assert!(User::login(&pool, "admin", "wrong").await.unwrap().is_none());
+        let stored = User::read(&pool, user.id).await.unwrap();
+        assert_eq!(stored.hashed_password, user.hashed_password);
+        assert!(is_legacy(&stored.hashed_password));
+    }
+}
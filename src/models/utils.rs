@@ -7,3 +7,16 @@ pub fn default_datetime() -> DateTime<Utc>{
     Utc::now()
 }
 
+
+pub fn default_template() -> String{
+    "index.html".to_string()
+}
+
+pub fn default_datatype() -> String{
+    "text".to_string()
+}
+
+/// Quotes an identifier (a table or column name) to use it in a query.
+pub fn quote(name: &str) -> String{
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
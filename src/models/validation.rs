@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::debug;
+
+// my own uses
+use super::{Constraints, Field, METADATA};
+
+static EMAIL: Lazy<Regex> = Lazy::new(|| built_in(r"^[^@\s]+@[^@\s]+\.[^@\s]+$"));
+static URL: Lazy<Regex> = Lazy::new(|| built_in(r"^[a-zA-Z][a-zA-Z0-9+.-]*://[^\s/?#]+[^\s]*$"));
+static TEL: Lazy<Regex> = Lazy::new(|| built_in(r"^\+?[0-9 ()./-]{3,}$"));
+static COLOR: Lazy<Regex> = Lazy::new(|| built_in(r"^#[0-9a-fA-F]{6}$"));
+static MONTH: Lazy<Regex> = Lazy::new(|| built_in(r"^[0-9]{4}-(0[1-9]|1[0-2])$"));
+static WEEK: Lazy<Regex> = Lazy::new(|| built_in(r"^[0-9]{4}-W(0[1-9]|[1-4][0-9]|5[0-3])$"));
+static NAME: Lazy<Regex> = Lazy::new(|| built_in(r"^[a-z][a-z0-9_]{0,62}$"));
+
+/// Datatypes a field can have.
+pub const DATATYPES: [&str; 18] = ["text", "email", "url", "tel", "number",
+    "range", "date", "time", "datetime-local", "month", "week", "color",
+    "checkbox", "select", "radio", "checkboxes", "password", "file"];
+
+/// Paths used by the application itself, that can not be used as form names.
+const RESERVED: [&str; 6] = ["admin", "api", "login", "logout", "results", "static"];
+
+/// Internal tables, that can not be used as form names either, as the table
+/// of a form is dropped and created again when its fields change.
+const TABLES: [&str; 12] = ["audit_log", "config", "fields", "forms", "notifications",
+    "protections", "revoked_tokens", "spent_challenges", "uploads", "users",
+    "webhook_deliveries", "webhooks"];
+
+/// Compiles one of the patterns above, built once on first use.
+fn built_in(pattern: &str) -> Regex{
+    Regex::new(pattern).expect("Wrong built-in pattern")
+}
+
+fn is_numeric(datatype: &str) -> bool{
+    matches!(datatype, "number" | "range")
+}
+
+/// Checks that `value` is a number, but not `inf` or `NaN`, that Rust would
+/// parse too.
+fn is_finite(value: &str) -> bool{
+    value.parse::<f64>()
+        .map(|number| number.is_finite())
+        .unwrap_or(false)
+}
+
+/// Checks that a name can be used for a form or a field, that is, as the
+/// name of a table or a column and as a path.
+pub fn is_valid_name(name: &str) -> bool{
+    NAME.is_match(name)
+}
+
+/// Checks that a name can be used for a field, which also must not clash with
+/// the metadata columns of the submissions.
+pub fn is_valid_field_name(name: &str) -> bool{
+    is_valid_name(name) && !METADATA.iter().any(|(meta, _)| *meta == name)
+}
+
+/// The `pattern` of a field as a regular expression that, like the HTML
+/// attribute, must match the whole value. `None` if it is not valid.
+pub(super) fn compile_pattern(pattern: &str) -> Option<Regex>{
+    Regex::new(&format!("^(?:{})$", pattern)).ok()
+}
+
+/// Checks that the `pattern` of a field is a regular expression, as it is
+/// matched against the whole value of every submission.
+pub fn is_valid_pattern(pattern: &str) -> bool{
+    compile_pattern(pattern).is_some()
+}
+
+/// Checks the definition of a field, wherever it comes from: a known
+/// datatype, the options of a choice field and a valid pattern. Returns what
+/// is wrong with it.
+pub fn validate_definition(datatype: &str, constraints: &Constraints) -> Result<(), String>{
+    if !DATATYPES.contains(&datatype){
+        return Err(format!("The datatype {} is not valid", datatype));
+    }
+    if matches!(datatype, "select" | "radio" | "checkboxes") && constraints.options.is_empty(){
+        return Err("Add the options to choose from".to_string());
+    }
+    if !constraints.pattern.as_deref().map(is_valid_pattern).unwrap_or(true){
+        return Err("The pattern is not a valid regular expression".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that a name can be used for a form, which also must not clash with
+/// the paths of the application, its internal tables or those of SQLite.
+/// Names never start with `_`, which is left for the temporary tables.
+pub fn is_valid_form_name(name: &str) -> bool{
+    is_valid_name(name) && !RESERVED.contains(&name) && !TABLES.contains(&name) &&
+        !name.starts_with("sqlite_")
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate>{
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime>{
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .ok()
+}
+
+fn parse_datetime(value: &str) -> Option<NaiveDateTime>{
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+}
+
+/// Checks that `value` is well formed for the `datatype` of the field.
+fn check_datatype(field: &Field, value: &str) -> Result<(), String>{
+    let valid = match field.get_datatype(){
+        "email"          => EMAIL.is_match(value),
+        "url"            => URL.is_match(value),
+        "tel"            => TEL.is_match(value),
+        "color"          => COLOR.is_match(value),
+        "month"          => MONTH.is_match(value),
+        "week"           => WEEK.is_match(value),
+        "number" | "range" => is_finite(value),
+        "date"           => parse_date(value).is_some(),
+        "time"           => parse_time(value).is_some(),
+        "datetime-local" => parse_datetime(value).is_some(),
+        "checkbox"       => matches!(value, "on" | "true" | "false" | "1" | "0"),
+        "select" | "radio" => is_option(field, value),
+        // The id of an upload, as the file itself comes apart
+        "file"           => value.parse::<i64>().is_ok(),
+        _                => true,
+    };
+    if valid{
+        Ok(())
+    }else{
+        Err(match field.get_datatype(){
+            "email"  => "Enter a valid email address".to_string(),
+            "url"    => "Enter a valid URL".to_string(),
+            "tel"    => "Enter a valid phone number".to_string(),
+            "number" | "range" => "Enter a number".to_string(),
+            "date"   => "Enter a valid date".to_string(),
+            "time"   => "Enter a valid time".to_string(),
+            "file"   => "Upload a file".to_string(),
+            "select" | "radio" => "Choose one of the options".to_string(),
+            datatype => format!("Enter a valid {}", datatype),
+        })
+    }
+}
+
+fn is_option(field: &Field, value: &str) -> bool{
+    field.get_constraints().options.iter().any(|option| option.value == value)
+}
+
+/// Checks the values of a `checkboxes` field, a JSON array of the values of
+/// its options.
+fn validate_choices(field: &Field, value: &str) -> Result<(), String>{
+    let chosen: Vec<String> = if value.trim().is_empty(){
+        Vec::new()
+    }else{
+        serde_json::from_str(value)
+            .map_err(|_| "Choose from the options".to_string())?
+    };
+    if chosen.is_empty(){
+        return if field.is_required(){
+            Err("Choose at least one option".to_string())
+        }else{
+            Ok(())
+        };
+    }
+    match chosen.iter().find(|value| !is_option(field, value)){
+        Some(value) => Err(format!("{} is not one of the options", value)),
+        None => Ok(()),
+    }
+}
+
+/// Compares two values of the field. Numbers, dates and times are compared
+/// once parsed, and everything else (months, weeks...) as ISO strings.
+fn compare(field: &Field, value: &str, limit: &str) -> Option<std::cmp::Ordering>{
+    match field.get_datatype(){
+        datatype if is_numeric(datatype) => {
+            let value = value.parse::<f64>().ok()?;
+            let limit = limit.parse::<f64>().ok()?;
+            value.partial_cmp(&limit)
+        },
+        "date" => Some(parse_date(value)?.cmp(&parse_date(limit)?)),
+        "time" => Some(parse_time(value)?.cmp(&parse_time(limit)?)),
+        "datetime-local" => Some(parse_datetime(value)?.cmp(&parse_datetime(limit)?)),
+        _ => Some(value.cmp(limit)),
+    }
+}
+
+/// Checks a single value against the field. An empty value is only an error
+/// when the field is required.
+pub fn validate_field(field: &Field, value: &str) -> Result<(), String>{
+    if field.get_datatype() == "checkboxes"{
+        return validate_choices(field, value);
+    }
+    if value.trim().is_empty(){
+        return if field.is_required(){
+            Err("This field is required".to_string())
+        }else{
+            Ok(())
+        };
+    }
+    check_datatype(field, value)?;
+    let constraints = field.get_constraints();
+    if let Some(min) = &constraints.min_value{
+        if compare(field, value, min) == Some(std::cmp::Ordering::Less){
+            return Err(format!("The minimum is {}", min));
+        }
+    }
+    if let Some(max) = &constraints.max_value{
+        if compare(field, value, max) == Some(std::cmp::Ordering::Greater){
+            return Err(format!("The maximum is {}", max));
+        }
+    }
+    let length = value.chars().count() as i64;
+    if let Some(min_length) = constraints.min_length{
+        if length < min_length{
+            return Err(format!("Use at least {} characters", min_length));
+        }
+    }
+    if let Some(max_length) = constraints.max_length{
+        if length > max_length{
+            return Err(format!("Use at most {} characters", max_length));
+        }
+    }
+    if constraints.pattern.is_some() &&
+            !field.get_pattern().map(|regex| regex.is_match(value)).unwrap_or(false){
+        return Err("The format is not valid".to_string());
+    }
+    if !constraints.allowed.is_empty() && !constraints.allowed.iter().any(|allowed| allowed == value){
+        return Err(format!("Choose one of {}", constraints.allowed.join(", ")));
+    }
+    Ok(())
+}
+
+/// Checks a submission against the fields of its form. Returns the error
+/// message of every invalid field, by field name.
+pub fn validate(fields: &[Field], data: &HashMap<String, String>) -> HashMap<String, String>{
+    let mut errors = HashMap::new();
+    for field in fields{
+        let value = data.get(field.get_name())
+            .map(|value| value.as_str())
+            .unwrap_or("");
+        if let Err(message) = validate_field(field, value){
+            debug!("{}: {}", field.get_name(), message);
+            errors.insert(field.get_name().to_string(), message);
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::{Choice, Constraints};
+
+    fn field(datatype: &str, required: bool, constraints: Constraints) -> Field{
+        Field::sample("value", datatype, required, constraints)
+    }
+
+    fn options() -> Constraints{
+        Constraints{
+            options: ["a", "b"].iter()
+                .map(|value| Choice{value: value.to_string(), label: value.to_uppercase()})
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_field_checks_required(){
+        let required = field("text", true, Constraints::default());
+        assert_eq!(validate_field(&required, " "), Err("This field is required".to_string()));
+        assert_eq!(validate_field(&required, "x"), Ok(()));
+        let optional = field("email", false, Constraints::default());
+        assert_eq!(validate_field(&optional, ""), Ok(()));
+    }
+
+    #[test]
+    fn validate_field_checks_datatypes(){
+        let email = field("email", false, Constraints::default());
+        assert_eq!(validate_field(&email, "me@example.com"), Ok(()));
+        assert_eq!(validate_field(&email, "me@example"),
+            Err("Enter a valid email address".to_string()));
+        let number = field("number", false, Constraints::default());
+        assert_eq!(validate_field(&number, "-2.5"), Ok(()));
+        assert_eq!(validate_field(&number, "two"), Err("Enter a number".to_string()));
+        assert_eq!(validate_field(&number, "inf"), Err("Enter a number".to_string()));
+        assert_eq!(validate_field(&number, "NaN"), Err("Enter a number".to_string()));
+        let date = field("date", false, Constraints::default());
+        assert_eq!(validate_field(&date, "2024-02-29"), Ok(()));
+        assert_eq!(validate_field(&date, "2023-02-29"), Err("Enter a valid date".to_string()));
+        let checkbox = field("checkbox", false, Constraints::default());
+        assert_eq!(validate_field(&checkbox, "on"), Ok(()));
+        assert_eq!(validate_field(&checkbox, "yes"), Err("Enter a valid checkbox".to_string()));
+    }
+
+    #[test]
+    fn validate_field_compares_numbers_as_numbers(){
+        let number = field("number", false, Constraints{
+            min_value: Some("2".to_string()),
+            max_value: Some("10".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&number, "9"), Ok(()));
+        assert_eq!(validate_field(&number, "1"), Err("The minimum is 2".to_string()));
+        assert_eq!(validate_field(&number, "11"), Err("The maximum is 10".to_string()));
+    }
+
+    #[test]
+    fn validate_field_compares_times_as_times(){
+        let time = field("time", false, Constraints{
+            min_value: Some("08:00".to_string()),
+            max_value: Some("17:00".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&time, "9:30"), Ok(()));
+        assert_eq!(validate_field(&time, "16:59:59"), Ok(()));
+        assert_eq!(validate_field(&time, "7:45"), Err("The minimum is 08:00".to_string()));
+        assert_eq!(validate_field(&time, "17:00:01"), Err("The maximum is 17:00".to_string()));
+        let datetime = field("datetime-local", false, Constraints{
+            max_value: Some("2024-05-01T17:00".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&datetime, "2024-05-01T9:30"), Ok(()));
+        assert_eq!(validate_field(&datetime, "2024-05-01T17:00:30"),
+            Err("The maximum is 2024-05-01T17:00".to_string()));
+    }
+
+    #[test]
+    fn validate_field_checks_lengths_in_characters(){
+        let text = field("text", false, Constraints{
+            min_length: Some(2),
+            max_length: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&text, "ñañ"), Ok(()));
+        assert_eq!(validate_field(&text, "a"), Err("Use at least 2 characters".to_string()));
+        assert_eq!(validate_field(&text, "abcd"), Err("Use at most 3 characters".to_string()));
+    }
+
+    #[test]
+    fn validate_field_matches_the_whole_pattern(){
+        let text = field("text", false, Constraints{
+            pattern: Some("[a-z]+|[0-9]+".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&text, "abc"), Ok(()));
+        assert_eq!(validate_field(&text, "123"), Ok(()));
+        assert_eq!(validate_field(&text, "abc1"), Err("The format is not valid".to_string()));
+    }
+
+    #[test]
+    fn validate_field_follows_changes_of_the_pattern(){
+        let mut text = field("text", false, Constraints::default());
+        assert_eq!(validate_field(&text, "abc"), Ok(()));
+        text.set_constraints(Constraints{
+            pattern: Some("[0-9]+".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&text, "123"), Ok(()));
+        assert_eq!(validate_field(&text, "abc"), Err("The format is not valid".to_string()));
+    }
+
+    #[test]
+    fn validate_field_checks_allowed_values(){
+        let text = field("text", false, Constraints{
+            allowed: vec!["yes".to_string(), "no".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(validate_field(&text, "no"), Ok(()));
+        assert_eq!(validate_field(&text, "maybe"), Err("Choose one of yes, no".to_string()));
+    }
+
+    #[test]
+    fn validate_definition_checks_datatypes_options_and_patterns(){
+        assert_eq!(validate_definition("text", &Constraints::default()), Ok(()));
+        assert_eq!(validate_definition("foo", &Constraints::default()),
+            Err("The datatype foo is not valid".to_string()));
+        assert_eq!(validate_definition("select", &options()), Ok(()));
+        for datatype in ["select", "radio", "checkboxes"]{
+            assert_eq!(validate_definition(datatype, &Constraints::default()),
+                Err("Add the options to choose from".to_string()));
+        }
+        let pattern = Constraints{
+            pattern: Some("[a-z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(validate_definition("text", &pattern),
+            Err("The pattern is not a valid regular expression".to_string()));
+    }
+
+    #[test]
+    fn validate_field_checks_options(){
+        let select = field("select", false, options());
+        assert_eq!(validate_field(&select, "a"), Ok(()));
+        assert_eq!(validate_field(&select, "c"), Err("Choose one of the options".to_string()));
+        let checkboxes = field("checkboxes", true, options());
+        assert_eq!(validate_field(&checkboxes, "[\"a\",\"b\"]"), Ok(()));
+        assert_eq!(validate_field(&checkboxes, "[]"),
+            Err("Choose at least one option".to_string()));
+        assert_eq!(validate_field(&checkboxes, ""),
+            Err("Choose at least one option".to_string()));
+        assert_eq!(validate_field(&checkboxes, "[\"a\",\"c\"]"),
+            Err("c is not one of the options".to_string()));
+        assert_eq!(validate_field(&checkboxes, "a"), Err("Choose from the options".to_string()));
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sqlx::{
+    sqlite::{
+        SqlitePool,
+        SqliteRow
+    },
+    query,
+    Row
+};
+use chrono::{
+    DateTime,
+    Duration,
+    Utc
+};
+use tracing::info;
+
+// my own uses
+use super::{Audit, Error, audit::to_json};
+
+/// Seconds before the first retry of a delivery, doubled on every retry.
+const RETRY_DELAY: i64 = 30;
+
+/// Longest wait between two attempts of a delivery, in seconds.
+const MAX_RETRY_DELAY: i64 = 6 * 3600;
+
+/// A URL that receives the submissions of a form as JSON.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Webhook{
+    pub id: i64,
+    pub form_id: i64,
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature of every request.
+    pub secret: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A request to a webhook, made or still to be made.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Delivery{
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    /// `pending`, `delivered` or `failed`.
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_status: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The webhook for the audit log, without its secret.
+fn audited(webhook: &Webhook) -> Option<Value>{
+    to_json(webhook).map(|mut value| {
+        if let Some(object) = value.as_object_mut(){
+            object.insert("secret".to_string(), Value::from("********"));
+        }
+        value
+    })
+}
+
+impl Webhook{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            form_id: row.get("form_id"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+            active: row.get("active"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    pub async fn create(pool: &SqlitePool, form_id: i64, url: &str, secret: &str)
+            -> Result<Self, Error>{
+        info!("create");
+        let now = Utc::now();
+        let sql = "INSERT INTO webhooks (form_id, url, secret, active, created_at,
+                   updated_at) VALUES ($1, $2, $3, TRUE, $4, $5) RETURNING *";
+        let created = query(sql)
+            .bind(form_id)
+            .bind(url)
+            .bind(secret)
+            .bind(now)
+            .bind(now)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "create", "webhook", &created.id.to_string(),
+            None, audited(&created)).await;
+        Ok(created)
+    }
+
+    pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("read");
+        let sql = "SELECT * FROM webhooks WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn read_by_form_id(pool: &SqlitePool, form_id: i64) -> Result<Vec<Self>, Error>{
+        info!("read_by_form_id");
+        let sql = "SELECT * FROM webhooks WHERE form_id = $1 ORDER BY id";
+        query(sql)
+            .bind(form_id)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn update(pool: &SqlitePool, webhook: &Self) -> Result<Self, Error>{
+        info!("update");
+        let before = Self::read(pool, webhook.id).await?;
+        let sql = "UPDATE webhooks SET url = $1, secret = $2, active = $3,
+                   updated_at = $4 WHERE id = $5 RETURNING *";
+        let updated = query(sql)
+            .bind(&webhook.url)
+            .bind(&webhook.secret)
+            .bind(webhook.active)
+            .bind(Utc::now())
+            .bind(webhook.id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "update", "webhook", &updated.id.to_string(),
+            audited(&before), audited(&updated)).await;
+        Ok(updated)
+    }
+
+    /// Deletes the webhook with all its deliveries.
+    pub async fn remove(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("remove");
+        let mut tx = pool.begin().await?;
+        let sql = "DELETE FROM webhooks WHERE id = $1 RETURNING *";
+        let removed = query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(&mut tx)
+            .await?;
+        query("DELETE FROM webhook_deliveries WHERE webhook_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Audit::record(pool, "delete", "webhook", &removed.id.to_string(),
+            audited(&removed), None).await;
+        Ok(removed)
+    }
+
+    /// Queues `payload` for every active webhook of the form.
+    pub async fn enqueue(pool: &SqlitePool, form_id: i64, event: &str, payload: &Value)
+            -> Result<u64, Error>{
+        info!("enqueue");
+        let sql = "INSERT INTO webhook_deliveries (webhook_id, event, payload,
+                   next_attempt_at) SELECT id, $1, $2, $3 FROM webhooks
+                   WHERE form_id = $4 AND active";
+        Ok(query(sql)
+            .bind(event)
+            .bind(payload.to_string())
+            .bind(Utc::now())
+            .bind(form_id)
+            .execute(pool)
+            .await?
+            .rows_affected())
+    }
+}
+
+impl Delivery{
+    fn from_row(row: SqliteRow) -> Self{
+        Self{
+            id: row.get("id"),
+            webhook_id: row.get("webhook_id"),
+            event: row.get("event"),
+            payload: row.get("payload"),
+            status: row.get("status"),
+            attempts: row.get("attempts"),
+            next_attempt_at: row.get("next_attempt_at"),
+            response_status: row.get("response_status"),
+            error: row.get("error"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    pub async fn read(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("read");
+        let sql = "SELECT * FROM webhook_deliveries WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// The last deliveries of a webhook, the newest first.
+    pub async fn read_by_webhook_id(pool: &SqlitePool, webhook_id: i64, limit: i64)
+            -> Result<Vec<Self>, Error>{
+        info!("read_by_webhook_id");
+        let sql = "SELECT * FROM webhook_deliveries WHERE webhook_id = $1
+                   ORDER BY id DESC LIMIT $2";
+        query(sql)
+            .bind(webhook_id)
+            .bind(limit)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Pending deliveries of active webhooks whose time has come, the
+    /// oldest first.
+    pub async fn read_due(pool: &SqlitePool, limit: i64) -> Result<Vec<Self>, Error>{
+        info!("read_due");
+        let sql = "SELECT * FROM webhook_deliveries WHERE status = 'pending'
+                   AND next_attempt_at <= $1 AND webhook_id IN
+                   (SELECT id FROM webhooks WHERE active)
+                   ORDER BY next_attempt_at LIMIT $2";
+        query(sql)
+            .bind(Utc::now())
+            .bind(limit)
+            .map(Self::from_row)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Records an attempt. A failed one is retried later with exponential
+    /// backoff until `max_attempts` is reached.
+    pub async fn attempted(&self, pool: &SqlitePool, response_status: Option<i64>,
+            error: Option<String>, max_attempts: i64) -> Result<Self, Error>{
+        info!("attempted");
+        let attempts = self.attempts + 1;
+        let delivered = error.is_none();
+        let status = if delivered{
+            "delivered"
+        }else if attempts >= max_attempts{
+            "failed"
+        }else{
+            "pending"
+        };
+        let delay = RETRY_DELAY
+            .saturating_mul(1i64 << (attempts - 1).clamp(0, 20))
+            .min(MAX_RETRY_DELAY);
+        let now = Utc::now();
+        let sql = "UPDATE webhook_deliveries SET status = $1, attempts = $2,
+                   next_attempt_at = $3, response_status = $4, error = $5,
+                   updated_at = $6 WHERE id = $7 RETURNING *";
+        query(sql)
+            .bind(status)
+            .bind(attempts)
+            .bind(now + Duration::seconds(delay))
+            .bind(response_status)
+            .bind(error)
+            .bind(now)
+            .bind(self.id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Queues the same payload again as a new delivery, keeping the log of
+    /// this one.
+    pub async fn redeliver(pool: &SqlitePool, id: i64) -> Result<Self, Error>{
+        info!("redeliver");
+        let sql = "INSERT INTO webhook_deliveries (webhook_id, event, payload,
+                   next_attempt_at) SELECT webhook_id, event, payload, $1
+                   FROM webhook_deliveries WHERE id = $2 RETURNING *";
+        let delivery = query(sql)
+            .bind(Utc::now())
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool)
+            .await?;
+        Audit::record(pool, "redeliver", "delivery", &id.to_string(),
+            None, to_json(&delivery)).await;
+        Ok(delivery)
+    }
+}
@@ -0,0 +1,427 @@
+use actix_web::{get, post, web, Result, error, Error, Responder, HttpResponse};
+use actix_web_lab::respond::Html;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport,
+    AsyncTransport,
+    Message,
+    Tokio1Executor,
+};
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use log::{debug, error};
+
+use crate::models::{self, Form, Field, Notification, Param};
+use crate::admin::{redirect, redirect_with_error, render};
+use crate::http::{auth::Admin, csrf};
+
+/// Seconds before the first retry of an email, doubled on every retry.
+const RETRY_DELAY: u64 = 5;
+
+/// Longest wait between two tries of an email, in seconds.
+const MAX_RETRY_DELAY: u64 = 600;
+
+/// Most retries of an email, as the form of the SMTP settings allows.
+const MAX_RETRIES: u32 = 10;
+
+/// Ways to secure the connection with the SMTP server: `tls` from the
+/// start, usually on port 465, `starttls` after connecting, usually on port
+/// 587, or `none`, only for a local server.
+const TLS_MODES: [&str; 3] = ["starttls", "tls", "none"];
+
+/// Settings of the SMTP server, kept in the `config` parameters.
+#[derive(Debug, Clone)]
+pub struct Smtp{
+    host: String,
+    port: u16,
+    tls: String,
+    username: String,
+    password: String,
+    from: String,
+    retries: u32,
+    /// Seconds before the first retry.
+    retry_delay: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmtpSchema{
+    host: String,
+    port: u16,
+    tls: String,
+    username: String,
+    password: String,
+    from: String,
+    retries: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestSchema{
+    to: String,
+}
+
+impl Smtp{
+    pub async fn load(pool: &SqlitePool) -> Result<Self, models::Error>{
+        let params = Param::get_all(pool).await?;
+        let get = |key: &str| params.get(key).cloned().unwrap_or_default();
+        Ok(Self{
+            host: get("smtp_host"),
+            port: get("smtp_port").parse().unwrap_or(587),
+            tls: get("smtp_tls"),
+            username: get("smtp_username"),
+            password: get("smtp_password"),
+            from: get("smtp_from"),
+            retries: get("smtp_retries").parse::<u32>().unwrap_or(3).min(MAX_RETRIES),
+            retry_delay: RETRY_DELAY,
+        })
+    }
+
+    /// Without a host no email is sent.
+    pub fn is_configured(&self) -> bool{
+        !self.host.is_empty()
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, models::Error>{
+        let builder = match self.tls.as_str(){
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host),
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?,
+        };
+        let builder = builder.port(self.port);
+        let builder = if self.username.is_empty(){
+            builder
+        }else{
+            builder.credentials(Credentials::new(self.username.clone(), self.password.clone()))
+        };
+        Ok(builder.build())
+    }
+
+    fn message(&self, to: &[String], subject: &str, body: String) -> Result<Message, models::Error>{
+        let mut builder = Message::builder()
+            .from(self.from.parse::<Mailbox>()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        for recipient in to{
+            builder = builder.to(recipient.parse::<Mailbox>()?);
+        }
+        Ok(builder.body(body)?)
+    }
+
+    /// Seconds to wait before the retry that follows `attempt`.
+    fn delay(&self, attempt: u32) -> u64{
+        2u64.checked_pow(attempt)
+            .map(|factor| self.retry_delay.saturating_mul(factor))
+            .unwrap_or(u64::MAX)
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// Sends an email, retrying up to `retries` times with a growing delay
+    /// while the server fails.
+    pub async fn send(&self, to: &[String], subject: &str, body: String) -> Result<(), models::Error>{
+        let message = self.message(to, subject, body)?;
+        let transport = self.transport()?;
+        let mut attempt = 0;
+        loop{
+            match transport.send(message.clone()).await{
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retries => {
+                    let delay = self.delay(attempt);
+                    debug!("Can not send email, retrying in {} seconds: {}", delay, e);
+                    actix_web::rt::time::sleep(Duration::from_secs(delay)).await;
+                    attempt += 1;
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Subject of the email from the template of the notification.
+fn subject(notification: &Notification, form: &Form, id: i64,
+        values: &HashMap<String, String>) -> String{
+    let mut ctx = tera::Context::new();
+    ctx.insert("name", &form.name);
+    ctx.insert("title", &form.title);
+    ctx.insert("id", &id);
+    ctx.insert("values", values);
+    Tera::one_off(&notification.subject, &ctx, false)
+        .map(|subject| subject.trim().replace(['\r', '\n'], " "))
+        .unwrap_or_else(|e| {
+            error!("Wrong subject for {}: {}", form.name, e);
+            format!("New submission of {}", form.title)
+        })
+}
+
+fn body(notification: &Notification, form: &Form, fields: &[Field], id: i64,
+        values: &HashMap<String, String>) -> String{
+    let mut body = format!("There is a new submission, #{}, of {}.\n", id, form.title);
+    if notification.include_answers{
+        body.push('\n');
+        for field in fields{
+            let value = values.get(field.get_name())
+                .map(|value| field.to_label(&Value::from(value.as_str())))
+                .unwrap_or_default();
+            body.push_str(&format!("{}: {}\n", field.get_label(), value));
+        }
+    }
+    body
+}
+
+/// Tells the recipients of the notification of the form about a new
+/// submission, without waiting for the email to be sent. Call it once the
+/// submission is saved.
+pub fn submitted(pool: SqlitePool, form: Form, fields: Vec<Field>, id: i64,
+        values: HashMap<String, String>){
+    actix_web::rt::spawn(async move {
+        notify(&pool, &form, &fields, id, &values).await;
+    });
+}
+
+async fn notify(pool: &SqlitePool, form: &Form, fields: &[Field], id: i64,
+        values: &HashMap<String, String>){
+    let notification = match Notification::read(pool, form.id).await{
+        Ok(notification) => notification,
+        Err(e) => {
+            error!("Can not read the notification of {}: {}", form.name, e);
+            return;
+        },
+    };
+    let recipients = notification.get_recipients();
+    if !notification.enabled || recipients.is_empty(){
+        return;
+    }
+    let smtp = match Smtp::load(pool).await{
+        Ok(smtp) if smtp.is_configured() => smtp,
+        Ok(_) => {
+            debug!("No SMTP server to notify {} of {}", id, form.name);
+            return;
+        },
+        Err(e) => {
+            error!("Can not read the SMTP settings: {}", e);
+            return;
+        },
+    };
+    let subject = subject(&notification, form, id, values);
+    let body = body(&notification, form, fields, id, values);
+    match smtp.send(&recipients, &subject, body).await{
+        Ok(_) => debug!("Notified {} of {}", id, form.name),
+        Err(e) => error!("Can not notify {} of {}: {}", id, form.name, e),
+    }
+}
+
+fn database_error(e: models::Error) -> Error{
+    error!("Database error: {}", e);
+    error::ErrorInternalServerError("Database error")
+}
+
+#[get("/smtp")]
+pub async fn get_smtp(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        params: web::Query<HashMap<String, String>>) -> Result<impl Responder, Error>{
+    let smtp = Smtp::load(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("host", &smtp.host);
+    ctx.insert("port", &smtp.port);
+    ctx.insert("tls", &smtp.tls);
+    ctx.insert("tls_modes", &TLS_MODES);
+    ctx.insert("username", &smtp.username);
+    ctx.insert("has_password", &!smtp.password.is_empty());
+    ctx.insert("from", &smtp.from);
+    ctx.insert("retries", &smtp.retries);
+    ctx.insert("error", &params.get("error"));
+    ctx.insert("sent", &params.contains_key("sent"));
+    Ok(Html(render(&template, "admin/smtp.html", &ctx)?))
+}
+
+/// Saves the settings of the SMTP server. An empty password keeps the
+/// current one.
+#[post("/smtp")]
+pub async fn post_smtp(_admin: Admin, pool: web::Data<SqlitePool>,
+        data: web::Form<SmtpSchema>) -> Result<HttpResponse, Error>{
+    if !TLS_MODES.contains(&data.tls.as_str()){
+        return Ok(redirect_with_error("/admin/smtp", "Unknown TLS mode"));
+    }
+    if data.retries > MAX_RETRIES{
+        return Ok(redirect_with_error("/admin/smtp", &format!(
+            "The retries can not be more than {}", MAX_RETRIES)));
+    }
+    let from = data.from.trim();
+    if !from.is_empty() && from.parse::<Mailbox>().is_err(){
+        return Ok(redirect_with_error("/admin/smtp", &format!(
+            "The sender {} is not a valid address", from)));
+    }
+    let mut params = vec![
+        ("smtp_host", data.host.trim().to_string()),
+        ("smtp_port", data.port.to_string()),
+        ("smtp_tls", data.tls.clone()),
+        ("smtp_username", data.username.trim().to_string()),
+        ("smtp_from", from.to_string()),
+        ("smtp_retries", data.retries.to_string()),
+    ];
+    if !data.password.is_empty(){
+        params.push(("smtp_password", data.password.clone()));
+    }
+    for (key, value) in params{
+        Param::set(pool.get_ref(), key, &value)
+            .await
+            .map_err(database_error)?;
+    }
+    Ok(redirect("/admin/smtp"))
+}
+
+/// Sends an email with the current settings, without retries, waiting for
+/// the result.
+#[post("/smtp/test")]
+pub async fn post_smtp_test(_admin: Admin, pool: web::Data<SqlitePool>,
+        data: web::Form<TestSchema>) -> Result<HttpResponse, Error>{
+    let smtp = Smtp::load(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    let smtp = Smtp{retries: 0, ..smtp};
+    if !smtp.is_configured(){
+        return Ok(redirect_with_error("/admin/smtp", "There is no SMTP server"));
+    }
+    let to = vec![data.to.trim().to_string()];
+    match smtp.send(&to, "Test email", "The SMTP settings work.\n".to_string()).await{
+        Ok(_) => Ok(redirect("/admin/smtp?sent=true")),
+        Err(e) => {
+            error!("Can not send test email to {}: {}", data.to, e);
+            Ok(redirect_with_error("/admin/smtp", &format!("Can not send the email: {}", e)))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Recipients and content of an email received by the stand-in.
+    type Mail = (Vec<String>, String);
+
+    /// A local SMTP server that answers the first `refusals` connections
+    /// with a 421 and takes every email after that.
+    async fn stand_in(refusals: usize) -> (u16, Arc<Mutex<Vec<Mail>>>, Arc<AtomicUsize>){
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mails = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (received, accepted) = (mails.clone(), connections.clone());
+        tokio::spawn(async move {
+            loop{
+                let (mut socket, _) = listener.accept().await.unwrap();
+                if accepted.fetch_add(1, Ordering::SeqCst) < refusals{
+                    socket.write_all(b"421 Busy\r\n").await.unwrap();
+                    continue;
+                }
+                let (reader, mut writer) = socket.split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 localhost\r\n").await.unwrap();
+                let mut recipients = Vec::new();
+                while let Some(line) = lines.next_line().await.unwrap(){
+                    let command = line.to_uppercase();
+                    let reply: &[u8] = if command.starts_with("RCPT TO:"){
+                        recipients.push(line[8..].trim_matches(['<', '>']).to_string());
+                        b"250 OK\r\n"
+                    }else if command == "DATA"{
+                        writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                        let mut content = String::new();
+                        while let Some(line) = lines.next_line().await.unwrap(){
+                            if line == "."{
+                                break;
+                            }
+                            content.push_str(&line);
+                            content.push('\n');
+                        }
+                        received.lock().unwrap()
+                            .push((std::mem::take(&mut recipients), content));
+                        b"250 OK\r\n"
+                    }else if command == "QUIT"{
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }else{
+                        b"250 OK\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            }
+        });
+        (port, mails, connections)
+    }
+
+    fn smtp(port: u16, retries: u32) -> Smtp{
+        Smtp{
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: "none".to_string(),
+            username: String::new(),
+            password: String::new(),
+            from: "asker@example.com".to_string(),
+            retries,
+            retry_delay: 0,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_a_limit(){
+        let smtp = Smtp{retry_delay: RETRY_DELAY, ..smtp(25, MAX_RETRIES)};
+        assert_eq!(smtp.delay(0), 5);
+        assert_eq!(smtp.delay(3), 40);
+        assert_eq!(smtp.delay(10), MAX_RETRY_DELAY);
+        assert_eq!(smtp.delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[actix_web::test]
+    async fn notify_sends_one_email_to_the_recipients(){
+        let (port, mails, _) = stand_in(0).await;
+        let pool = crate::migrations::test_pool().await;
+        let (form, _) = crate::migrations::test_form(&pool, "contact", vec![]).await;
+        Notification{
+            form_id: form.id,
+            enabled: true,
+            recipients: "one@example.com, two@example.com".to_string(),
+            subject: "{{ title }} #{{ id }} from {{ values.name }}".to_string(),
+            include_answers: true,
+            updated_at: chrono::Utc::now(),
+        }.save(&pool).await.unwrap();
+        for (key, value) in [("smtp_host", "127.0.0.1".to_string()),
+                ("smtp_port", port.to_string()), ("smtp_tls", "none".to_string()),
+                ("smtp_from", "asker@example.com".to_string())]{
+            Param::set(&pool, key, &value).await.unwrap();
+        }
+        let values = HashMap::from([("name".to_string(), "Me".to_string())]);
+        notify(&pool, &form, &[], 7, &values).await;
+        let mails = mails.lock().unwrap();
+        assert_eq!(mails.len(), 1);
+        let (recipients, content) = &mails[0];
+        assert_eq!(recipients, &["one@example.com", "two@example.com"]);
+        assert!(content.contains("Subject: Contact #7 from Me\n"));
+        assert!(content.contains("There is a new submission, #7, of Contact."));
+    }
+
+    #[actix_web::test]
+    async fn send_retries_refused_connections(){
+        let (port, mails, connections) = stand_in(2).await;
+        let to = vec!["one@example.com".to_string()];
+        assert!(smtp(port, 1).send(&to, "Test", "Body".to_string()).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert!(mails.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn send_succeeds_once_the_server_takes_the_email(){
+        let (port, mails, connections) = stand_in(2).await;
+        let to = vec!["one@example.com".to_string()];
+        smtp(port, 2).send(&to, "Test", "Body".to_string()).await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(mails.lock().unwrap().len(), 1);
+    }
+}
@@ -1,21 +1,109 @@
-use actix_web::{get, post, web, Result, error, Error, Responder};
+use actix_web::{get, post, web, Result, error, Error, Responder,
+    HttpRequest, HttpResponse, http::{StatusCode, header}};
+use actix_session::Session;
+use serde::Deserialize;
 use actix_web_lab::respond::Html;
-use sqlx::{sqlite::{SqlitePool, SqliteRow}, query, Row};
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use serde_json::Value;
 use tera::Tera;
 use std::collections::HashMap;
 use log::{debug, error};
-use actix_web_httpauth::extractors::basic::BasicAuth;
-use crate::config::{Configuration, Table};
+use crate::models::{Form, Field, Filter, FilterKind, Protection, User};
+use crate::config::Configuration;
+use crate::http::{auth::{Admin, USER_ID}, client::origin, csrf};
+use crate::{notify, uploads, webhooks};
+use crate::spam::{self, Trap, Verdict};
 
-fn from_row(table: &Table, rows: Vec<SqliteRow>) -> Vec<Vec<String>>{
+#[derive(Debug, Deserialize)]
+pub struct Next{
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Login{
+    name: String,
+    password: String,
+    next: Option<String>,
+}
+
+/// Only paths inside this site are followed after login. Browsers take a
+/// `\` as a `/`, so `/\host` would leave the site too.
+fn safe_next(next: &Option<String>) -> String{
+    match next{
+        Some(next) if next.starts_with('/') && !next.starts_with("//") &&
+            !next.contains('\\') => next.to_string(),
+        _ => "/admin".to_string(),
+    }
+}
+
+fn render_login(template: &Tera, name: &str, next: &str, message: Option<&str>) -> Result<String, Error>{
+    let mut ctx = csrf::context();
+    ctx.insert("name", name);
+    ctx.insert("next", next);
+    ctx.insert("error", &message);
+    template.render("login.html", &ctx)
+        .map_err(|_| error::ErrorInternalServerError("Template error"))
+}
+
+#[get("/login")]
+pub async fn get_login(template: web::Data<Tera>, query: web::Query<Next>) -> Result<impl Responder, Error>{
+    let next = safe_next(&query.next);
+    Ok(Html(render_login(&template, "", &next, None)?))
+}
+
+#[post("/login")]
+pub async fn post_login(template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        session: Session, login: web::Form<Login>) -> Result<HttpResponse, Error>{
+    let next = safe_next(&login.next);
+    let user = User::login(pool.get_ref(), &login.name, &login.password)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    match user{
+        Some(user) => {
+            debug!("User {} logged in", user.name);
+            session.renew();
+            session.insert(USER_ID, user.get_id())?;
+            Ok(HttpResponse::Found()
+                .insert_header((header::LOCATION, next))
+                .finish())
+        },
+        None => {
+            debug!("Login failed for {}", login.name);
+            let html = render_login(&template, &login.name, &next,
+                Some("Wrong name or password"))?;
+            Ok(HttpResponse::Unauthorized()
+                .content_type("text/html; charset=utf-8")
+                .body(html))
+        },
+    }
+}
+
+#[post("/logout")]
+pub async fn post_logout(session: Session) -> Result<HttpResponse, Error>{
+    session.purge();
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, "/login"))
+        .finish())
+}
+
+/// Metadata of the submissions shown in the results, with their headers.
+const COLUMNS: [(&str, &str); 4] = [("id", "#"), ("submitted_at", "Submitted"),
+    ("ip", "IP"), ("user_agent", "User agent")];
+
+/// Submissions as rows of text, the metadata of `COLUMNS` first and then the
+/// value of every field.
+fn from_row(fields: &[Field], rows: Vec<SqliteRow>) -> Vec<Vec<String>>{
     let mut results: Vec<Vec<String>> = Vec::new();
     for row in rows{
+        let values = Form::row_to_json(fields, &row);
         let mut map: Vec<String> = Vec::new();
-        for field in table.fields.as_slice(){
-            let name: &str = &field.name;
-            let value = match row.try_get(name){
-                Ok(val) => val,
-                Err(_) => "".to_string(),
+        for (name, _) in COLUMNS{
+            map.push(values.get(name).map(to_text).unwrap_or_default());
+        }
+        for field in fields{
+            let value = match values.get(field.get_name()){
+                Some(val) => field.to_label(val),
+                None => "".to_string(),
             };
             map.push(value);
         }
@@ -24,105 +112,390 @@ fn from_row(table: &Table, rows: Vec<SqliteRow>) -> Vec<Vec<String>>{
     results
 }
 
+fn to_text(value: &Value) -> String{
+    match value{
+        Value::Null => "".to_string(),
+        Value::String(value) => value.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Link to a page of the results keeping filters and order.
+fn page_url(table: &str, query: &str, page: i64, per_page: i64) -> String{
+    let separator = if query.is_empty() { "" } else { "&" };
+    format!("/results/{}?{}{}page={}&per_page={}", table, query, separator, page, per_page)
+}
+
+/// Renders a form with the values already filled in and the error message of
+/// every invalid field, to be posted to `action`.
+fn render_form(template: &Tera, form: &Form, fields: &[Field], action: &str,
+        values: &HashMap<String, String>, errors: &HashMap<String, String>,
+        trap: Option<&Trap>) -> Result<String, Error>{
+    // Options checked in every `checkboxes` field
+    let chosen: HashMap<&str, Vec<String>> = fields.iter()
+        .filter(|field| field.get_datatype() == "checkboxes")
+        .map(|field| (field.get_name(), values.get(field.get_name())
+            .map(|value| Field::chosen(value))
+            .unwrap_or_default()))
+        .collect();
+    let mut ctx = csrf::context();
+    ctx.insert("table", &form.name);
+    ctx.insert("action", action);
+    ctx.insert("trap", &trap);
+    ctx.insert("title", &form.title);
+    ctx.insert("instructions", &form.instructions);
+    ctx.insert("fields", &fields);
+    ctx.insert("values", values);
+    ctx.insert("chosen", &chosen);
+    ctx.insert("errors", errors);
+    debug!("Template: {}", form.template);
+    template.render(&form.template, &ctx)
+        .map_err(|e| {
+            error!("Can not render {} with {}: {}", form.name, form.template, e);
+            error::ErrorInternalServerError("Template error")
+        })
+}
+
 #[get("/{table}")]
-pub async fn get_results(auth: BasicAuth, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
-        configuration: web::Data<Configuration>, table_path: web::Path<String>) -> Result<impl Responder, Error>{
+pub async fn get_results(_admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        table_path: web::Path<String>, params: web::Query<HashMap<String, String>>)
+        -> Result<impl Responder, Error>{
     let table_name = table_path.into_inner();
-    let html = if configuration.username != auth.user_id() || 
-            auth.password().is_none() ||
-            auth.password().unwrap() != configuration.password{
-        let ctx = tera::Context::new();
-        template.render("results.html", &ctx)
-            .map_err(|_| error::ErrorInternalServerError("Template error"))
-    }else{
-        match configuration.get_table(&table_name){
-            Some(table) => {
-                let sql = format!("SELECT * from {} LIMIT {} OFFSET {}", &table_name, 10, 0);
-                let rows = query(&sql)
-                    .fetch_all(pool.get_ref())
-                    .await
-                    .unwrap();
-                let data = from_row(table, rows);
-                //debug!("Resultados: {:?}", results);
-                let mut ctx = tera::Context::new();
-                ctx.insert("table", &table_name);
-                ctx.insert("title", &table.title);
-                ctx.insert("instructions", &table.instructions);
-                ctx.insert("fields", &table.fields);
-                ctx.insert("data", &data);
-                debug!("Template: {}", "results.html");
-                template.render("results.html", &ctx)
-                    .map_err(|_| error::ErrorInternalServerError("Template error"))
-                
-            },
-            None => 
-                template.render("404.html", &tera::Context::new())
-                    .map_err(|_| error::ErrorInternalServerError("Template error"))
-        }
+    let html = match Form::read_by_name(pool.get_ref(), &table_name).await{
+        Ok(form) => {
+            let fields = form.get_fields(pool.get_ref())
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let filter = Filter::from_query(&fields, &params);
+            let per_page = params.get("per_page")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(10)
+                .clamp(1, 500);
+            let total = form.count(pool.get_ref(), &fields, &filter)
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let pages = ((total + per_page - 1) / per_page).max(1);
+            let page = params.get("page")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(1)
+                .clamp(1, pages);
+            let rows = form.read_rows(pool.get_ref(), &fields, &filter,
+                    Some(per_page), (page - 1) * per_page)
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let data = from_row(&fields, rows);
+            //debug!("Resultados: {:?}", results);
+            // Filters without the order, to build the sort links
+            let mut unsorted = filter.clone();
+            unsorted.sort = None;
+            unsorted.descending = false;
+            let mut sort_urls = HashMap::new();
+            let names = COLUMNS.iter()
+                .map(|(name, _)| *name)
+                .chain(fields.iter().map(|field| field.get_name()));
+            for name in names{
+                let mut sorted = unsorted.clone();
+                sorted.sort = Some(name.to_string());
+                sorted.descending = filter.sort.as_deref() == Some(name) &&
+                    !filter.descending;
+                sort_urls.insert(name.to_string(),
+                    page_url(&form.name, &sorted.to_query(), 1, per_page));
+            }
+            let kinds: HashMap<String, FilterKind> = fields.iter()
+                .map(|field| (field.get_name().to_string(), FilterKind::of(field)))
+                .collect();
+            // Positions of the columns of files in the rows, to link them
+            let file_columns: Vec<usize> = fields.iter()
+                .enumerate()
+                .filter(|(_, field)| field.get_datatype() == "file")
+                .map(|(index, _)| COLUMNS.len() + index)
+                .collect();
+            let query = filter.to_query();
+            let mut ctx = csrf::context();
+            ctx.insert("table", &form.name);
+            ctx.insert("title", &form.title);
+            ctx.insert("instructions", &form.instructions);
+            ctx.insert("fields", &fields);
+            ctx.insert("columns", &COLUMNS);
+            ctx.insert("data", &data);
+            ctx.insert("file_columns", &file_columns);
+            ctx.insert("filter", &filter);
+            ctx.insert("filtered", &!filter.is_empty());
+            ctx.insert("kinds", &kinds);
+            ctx.insert("sort_urls", &sort_urls);
+            ctx.insert("total", &total);
+            ctx.insert("page", &page);
+            ctx.insert("pages", &pages);
+            ctx.insert("per_page", &per_page);
+            ctx.insert("query", &query);
+            if page > 1{
+                ctx.insert("previous_url", &page_url(&form.name, &query, page - 1, per_page));
+            }
+            if page < pages{
+                ctx.insert("next_url", &page_url(&form.name, &query, page + 1, per_page));
+            }
+            debug!("Template: {}", "results.html");
+            template.render("results.html", &ctx)
+                .map_err(|_| error::ErrorInternalServerError("Template error"))
+
+        },
+        Err(_) =>
+            template.render("404.html", &tera::Context::new())
+                .map_err(|_| error::ErrorInternalServerError("Template error"))
     };
-    let html_content = html.unwrap();
+    let html_content = html?;
     debug!("Content: {}", &html_content);
     Ok(Html(html_content))
 }
 
 #[get("/{table}")]
-pub async fn get_form(template: web::Data<Tera>, configuration: web::Data<Configuration>, table_path: web::Path<String>) -> Result<impl Responder, Error>{
+pub async fn get_form(template: web::Data<Tera>, pool: web::Data<SqlitePool>, table_path: web::Path<String>) -> Result<impl Responder, Error>{
     let table_name = table_path.into_inner();
-    let html = match configuration.get_table(&table_name){
-        Some(table) => {
-            let mut ctx = tera::Context::new();
-            ctx.insert("table", &table_name);
-            ctx.insert("title", &table.title);
-            ctx.insert("instructions", &table.instructions);
-            ctx.insert("fields", &table.fields);
-            debug!("Template: {}", table.template);
-            template.render(&table.template, &ctx)
-                .map_err(|_| error::ErrorInternalServerError("Template error"))
-            
+    let html = match Form::read_by_name(pool.get_ref(), &table_name).await{
+        Ok(form) => {
+            let fields = form.get_fields(pool.get_ref())
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let protection = Protection::read(pool.get_ref(), form.id)
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let trap = spam::trap(pool.get_ref(), &form, &fields, &protection).await;
+            render_form(&template, &form, &fields, &format!("/{}", form.name),
+                &HashMap::new(), &HashMap::new(), Some(&trap))
         },
-        None => 
+        Err(_) =>
             template.render("404.html", &tera::Context::new())
                 .map_err(|_| error::ErrorInternalServerError("Template error"))
-    }.unwrap();
+    }?;
     debug!("Content: {}", &html);
     Ok(Html(html))
 }
 
 #[post("/{table}")]
-pub async fn post_form(template: web::Data<Tera>, pool: web::Data<SqlitePool>,
-        configuration: web::Data<Configuration>, table_path: web::Path<String>,
-        form: web::Form<HashMap<String, String>>) 
+pub async fn post_form(req: HttpRequest, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        conf: web::Data<Configuration>, table_path: web::Path<String>,
+        payload: web::Payload)
         -> Result<impl Responder, Error>{
     let table_name = table_path.into_inner();
-    debug!("Content: {:?}", form);
-    let html = match configuration.get_table(&table_name){
-        Some(table) => {
-            let sql = table.insert(&table_name);
-            let mut sql_query = query::<sqlx::Sqlite>(&sql);
-            for field in table.fields.as_slice(){
-                match form.get(&field.name) {
-                    Some(value) => sql_query = sql_query.bind(value),
-                    None => sql_query = sql_query.bind(""),
-                }
-            }
-            match sql_query.execute(pool.get_ref()).await{
-                Ok(_)  => template.render("200.html", &tera::Context::new())
-                            .map_err(|_| error::ErrorInternalServerError("Template error")),
-                Err(e) => {
-                    error!("No pude: {}", e);
-                    let context = tera::Context::new();
-                    let template_file = if e.to_string().to_lowercase().contains("unique"){
-                        "500.html"
-                    }else{
-                        "500.html"
-                    };
-                    template.render(template_file, &context)
-                            .map_err(|_| error::ErrorInternalServerError("Template error"))
+    let (html, status) = match Form::read_by_name(pool.get_ref(), &table_name).await{
+        Ok(form) => {
+            let fields = form.get_fields(pool.get_ref())
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let mut submission = uploads::read(&req, payload, &fields).await?;
+            debug!("Submission to {} with {} values", form.name, submission.data.len());
+            let action = format!("/{}", form.name);
+            let protection = Protection::read(pool.get_ref(), form.id)
+                .await
+                .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+            let mut trap = spam::trap(pool.get_ref(), &form, &fields, &protection).await;
+            let verdict = spam::check(pool.get_ref(), &form, &fields, &protection,
+                &submission.data).await;
+            let errors = uploads::validate(&fields, &submission);
+            if verdict == Verdict::Bot{
+                // Bots are told it worked, so they do not try harder
+                debug!("Honeypot of {} filled", form.name);
+                (template.render("200.html", &tera::Context::new())
+                        .map_err(|_| error::ErrorInternalServerError("Template error")),
+                    StatusCode::OK)
+            }else if verdict == Verdict::TooFast{
+                debug!("Time trap of {} not passed", form.name);
+                trap.error = Some("The form was sent too fast. Please check it and send it again".to_string());
+                (render_form(&template, &form, &fields, &action, &submission.data, &errors, Some(&trap)),
+                    StatusCode::UNPROCESSABLE_ENTITY)
+            }else if verdict == Verdict::Unproven{
+                debug!("Proof of work of {} not valid", form.name);
+                trap.error = Some("The form could not be verified. Please enable JavaScript and send it again".to_string());
+                trap.token = submission.data.get(spam::TOKEN_FIELD).cloned().or(trap.token);
+                (render_form(&template, &form, &fields, &action, &submission.data, &errors, Some(&trap)),
+                    StatusCode::UNPROCESSABLE_ENTITY)
+            }else if !errors.is_empty(){
+                // The time already spent on the form still counts
+                trap.token = submission.data.get(spam::TOKEN_FIELD).cloned().or(trap.token);
+                (render_form(&template, &form, &fields, &action, &submission.data, &errors, Some(&trap)),
+                    StatusCode::UNPROCESSABLE_ENTITY)
+            }else{
+                let inserted = match uploads::store(pool.get_ref(), &conf, &form, &mut submission).await{
+                    Ok(stored) => {
+                        let inserted = form.insert(pool.get_ref(), &fields, &submission.data,
+                            &origin(&req, &conf)).await;
+                        if inserted.is_err(){
+                            uploads::discard(pool.get_ref(), &conf, stored).await;
+                        }
+                        inserted
+                    },
+                    Err(e) => Err(e.to_string().into()),
+                };
+                match inserted{
+                    Ok(id) => {
+                        webhooks::submitted(pool.get_ref(), &form, &fields, id).await;
+                        notify::submitted(pool.get_ref().clone(), form.clone(), fields.clone(),
+                            id, submission.data.clone());
+                        (template.render("200.html", &tera::Context::new())
+                                .map_err(|_| error::ErrorInternalServerError("Template error")),
+                            StatusCode::OK)
+                    },
+                    Err(e) => match form.duplicated(&fields, &e){
+                        Some(duplicated) => {
+                            debug!("Duplicated value for {}", duplicated.0);
+                            let errors = HashMap::from([duplicated]);
+                            trap.token = submission.data.get(spam::TOKEN_FIELD).cloned().or(trap.token);
+                            (render_form(&template, &form, &fields, &action, &submission.data, &errors,
+                                    Some(&trap)),
+                                StatusCode::CONFLICT)
+                        },
+                        None => {
+                            error!("No pude: {}", e);
+                            (template.render("500.html", &tera::Context::new())
+                                    .map_err(|_| error::ErrorInternalServerError("Template error")),
+                                StatusCode::INTERNAL_SERVER_ERROR)
+                        }
+                    }
                 }
             }
         },
-        None =>  template.render("500.html", &tera::Context::new())
-                .map_err(|_| error::ErrorInternalServerError("Template error"))
-    }.unwrap();
+        Err(_) =>  (template.render("404.html", &tera::Context::new())
+                .map_err(|_| error::ErrorInternalServerError("Template error")),
+            StatusCode::NOT_FOUND)
+    };
+    Ok(Html(html?).customize().with_status(status))
+}
+
+/// Reads a form and one of its submissions, for the pages of a submission.
+async fn read_submission(pool: &SqlitePool, table: &str, id: i64)
+        -> Result<(Form, Vec<Field>, HashMap<String, String>), Error>{
+    let form = Form::read_by_name(pool, table)
+        .await
+        .map_err(|_| error::ErrorNotFound("Form not found"))?;
+    let fields = form.get_fields(pool)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    let row = form.read_row(pool, id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Submission not found"))?;
+    let values = Form::row_to_json(&fields, &row)
+        .iter()
+        .map(|(key, value)| (key.to_string(), to_text(value)))
+        .collect();
+    Ok((form, fields, values))
+}
+
+/// A submission in the form it was sent with, to be corrected.
+#[get("/{table}/{id:\\d+}")]
+pub async fn get_submission(_admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, i64)>) -> Result<impl Responder, Error>{
+    let (table, id) = path.into_inner();
+    let (form, fields, values) = read_submission(pool.get_ref(), &table, id).await?;
+    let action = format!("/results/{}/{}", form.name, id);
+    Ok(Html(render_form(&template, &form, &fields, &action, &values, &HashMap::new(), None)?))
+}
+
+#[post("/{table}/{id:\\d+}")]
+pub async fn post_submission(_admin: Admin, req: HttpRequest, template: web::Data<Tera>,
+        pool: web::Data<SqlitePool>, conf: web::Data<Configuration>,
+        path: web::Path<(String, i64)>, payload: web::Payload) -> Result<HttpResponse, Error>{
+    let (table, id) = path.into_inner();
+    let (form, fields, values) = read_submission(pool.get_ref(), &table, id).await?;
+    let action = format!("/results/{}/{}", form.name, id);
+    let mut submission = uploads::read(&req, payload, &fields).await?;
+    let replaced = uploads::replaced(pool.get_ref(), &fields, &values, &submission).await;
+    // Files not sent again are kept
+    for field in fields.iter().filter(|field| field.get_datatype() == "file"){
+        if let Some(value) = values.get(field.get_name()){
+            submission.data.entry(field.get_name().to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    let errors = uploads::validate(&fields, &submission);
+    if !errors.is_empty(){
+        return Ok(HttpResponse::UnprocessableEntity()
+            .content_type("text/html; charset=utf-8")
+            .body(render_form(&template, &form, &fields, &action, &submission.data, &errors, None)?));
+    }
+    let stored = uploads::store(pool.get_ref(), &conf, &form, &mut submission).await?;
+    let data = submission.data;
+    let updated = form.update_row(pool.get_ref(), &fields, id, &data).await;
+    if updated.is_err(){
+        uploads::discard(pool.get_ref(), &conf, stored).await;
+    }else{
+        uploads::discard(pool.get_ref(), &conf, replaced).await;
+    }
+    match updated{
+        Ok(_) => {
+            debug!("Submission {} of {} updated", id, form.name);
+            Ok(HttpResponse::Found()
+                .insert_header((header::LOCATION, format!("/results/{}", form.name)))
+                .finish())
+        },
+        Err(e) => match form.duplicated(&fields, &e){
+            Some(duplicated) => {
+                let errors = HashMap::from([duplicated]);
+                Ok(HttpResponse::Conflict()
+                    .content_type("text/html; charset=utf-8")
+                    .body(render_form(&template, &form, &fields, &action, &data, &errors, None)?))
+            },
+            None => {
+                error!("Can not update submission {} of {}: {}", id, form.name, e);
+                Err(error::ErrorInternalServerError("Database error"))
+            },
+        },
+    }
+}
+
+/// Asks for confirmation before deleting a submission.
+#[get("/{table}/{id:\\d+}/delete")]
+pub async fn get_delete_submission(_admin: Admin, template: web::Data<Tera>,
+        pool: web::Data<SqlitePool>, path: web::Path<(String, i64)>) -> Result<impl Responder, Error>{
+    let (table, id) = path.into_inner();
+    let (form, fields, values) = read_submission(pool.get_ref(), &table, id).await?;
+    let mut ctx = csrf::context();
+    ctx.insert("table", &form.name);
+    ctx.insert("title", &form.title);
+    ctx.insert("id", &id);
+    ctx.insert("fields", &fields);
+    ctx.insert("values", &values);
+    debug!("Template: {}", "delete.html");
+    let html = template.render("delete.html", &ctx)
+        .map_err(|_| error::ErrorInternalServerError("Template error"))?;
     Ok(Html(html))
 }
+
+#[post("/{table}/{id:\\d+}/delete")]
+pub async fn post_delete_submission(_admin: Admin, pool: web::Data<SqlitePool>,
+        path: web::Path<(String, i64)>) -> Result<HttpResponse, Error>{
+    let (table, id) = path.into_inner();
+    let (form, _, _) = read_submission(pool.get_ref(), &table, id).await?;
+    form.delete_row(pool.get_ref(), id)
+        .await
+        .map_err(|_| error::ErrorInternalServerError("Database error"))?;
+    debug!("Submission {} of {} deleted", id, form.name);
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, format!("/results/{}", form.name)))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn next(path: &str) -> String{
+        safe_next(&Some(path.to_string()))
+    }
+
+    #[test]
+    fn safe_next_follows_local_paths(){
+        assert_eq!(next("/admin/forms"), "/admin/forms");
+        assert_eq!(next("/results/contact?page=2"), "/results/contact?page=2");
+    }
+
+    #[test]
+    fn safe_next_refuses_other_sites(){
+        assert_eq!(safe_next(&None), "/admin");
+        assert_eq!(next(""), "/admin");
+        assert_eq!(next("https://example.com"), "/admin");
+        assert_eq!(next("//example.com"), "/admin");
+        assert_eq!(next("/\\example.com"), "/admin");
+        assert_eq!(next("\\\\example.com"), "/admin");
+        assert_eq!(next("admin"), "/admin");
+    }
+}
@@ -0,0 +1,374 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error,
+    http::{header, Method},
+    web,
+    Error,
+    HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use log::{debug, error};
+
+use crate::config::Configuration;
+use crate::http::{client::origin, csrf::same};
+use crate::models::{Challenge, Form, Field, Param, Protection};
+
+/// Name of the hidden input with the time the form was shown, signed.
+pub const TOKEN_FIELD: &str = "_token";
+
+/// Name of the hidden input with the proof-of-work challenge.
+pub const CHALLENGE_FIELD: &str = "_pow_challenge";
+
+/// Name of the hidden input with the solution found by `pow.js`.
+pub const SOLUTION_FIELD: &str = "_pow_solution";
+
+/// Seconds a token of the time trap or a challenge is valid.
+const MAX_TOKEN_AGE: i64 = 24 * 3600;
+
+/// Buckets kept before the full ones are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+/// What `get_form` adds to a public form, rendered by `trap.html`.
+#[derive(Debug, Serialize, Default)]
+pub struct Trap{
+    /// Name of the hidden field that must be left empty.
+    pub honeypot: Option<String>,
+    /// Signed time the form was shown.
+    pub token: Option<String>,
+    /// Signed proof-of-work challenge for `pow.js`.
+    pub challenge: Option<String>,
+    pub error: Option<String>,
+}
+
+/// What a submission looks like.
+#[derive(Debug, PartialEq)]
+pub enum Verdict{
+    Human,
+    /// It filled the honeypot.
+    Bot,
+    /// It came sooner than `min_fill_seconds` or without a valid token.
+    TooFast,
+    /// It has no valid solution of a proof-of-work challenge.
+    Unproven,
+}
+
+fn signature(secret: &str, message: &str) -> String{
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Seconds since the token was issued for the form, if it is valid.
+fn token_age(secret: &str, form: &str, token: &str) -> Option<i64>{
+    let (issued_at, signed) = token.split_once('.')?;
+    let issued_at = issued_at.parse::<i64>().ok()?;
+    if !same(&signature(secret, &format!("{}.{}", form, issued_at)), signed){
+        return None;
+    }
+    let age = Utc::now().timestamp() - issued_at;
+    (0..=MAX_TOKEN_AGE).contains(&age).then(|| age)
+}
+
+/// A challenge for the form: when it was issued, a random nonce and the
+/// leading zero bits the hash of the solution must have, signed.
+fn challenge(secret: &str, form: &str, difficulty: i64) -> String{
+    let content = format!("{}.{}.{}", Utc::now().timestamp(),
+        uuid::Uuid::new_v4().simple(), difficulty);
+    let signed = signature(secret, &format!("pow.{}.{}", form, content));
+    format!("{}.{}", content, signed)
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32{
+    let mut bits = 0;
+    for byte in hash{
+        bits += byte.leading_zeros();
+        if *byte != 0{
+            break;
+        }
+    }
+    bits
+}
+
+/// Nonce of the challenge if it is signed for the form with at least the
+/// current difficulty, has not expired and the SHA-256 of `challenge:solution`
+/// starts with that many zero bits.
+fn proven(secret: &str, form: &str, difficulty: i64, challenge: &str, solution: &str)
+        -> Option<String>{
+    let (content, signed) = challenge.rsplit_once('.')?;
+    if !same(&signature(secret, &format!("pow.{}.{}", form, content)), signed){
+        return None;
+    }
+    let mut parts = content.split('.');
+    let issued_at = parts.next()?.parse::<i64>().ok()?;
+    let nonce = parts.next()?;
+    let required = parts.next()?.parse::<i64>().ok()?;
+    let age = Utc::now().timestamp() - issued_at;
+    if required < difficulty || !(0..=MAX_TOKEN_AGE).contains(&age){
+        return None;
+    }
+    let hash = Sha256::digest(format!("{}:{}", challenge, solution).as_bytes());
+    (leading_zero_bits(&hash) as i64 >= required).then(|| nonce.to_string())
+}
+
+/// Name of the honeypot, unless it is off or a field of the form took it.
+fn honeypot<'a>(protection: &'a Protection, fields: &[Field]) -> Option<&'a str>{
+    let name = protection.honeypot_name.as_str();
+    (protection.honeypot && !fields.iter().any(|field| field.get_name() == name))
+        .then(|| name)
+}
+
+/// The hidden inputs of a public form.
+pub async fn trap(pool: &SqlitePool, form: &Form, fields: &[Field], protection: &Protection)
+        -> Trap{
+    let token = if protection.min_fill_seconds > 0{
+        let secret = Param::get_form_secret(pool).await;
+        let issued_at = Utc::now().timestamp();
+        Some(format!("{}.{}", issued_at,
+            signature(&secret, &format!("{}.{}", form.name, issued_at))))
+    }else{
+        None
+    };
+    let challenge = if protection.pow_difficulty > 0{
+        let secret = Param::get_form_secret(pool).await;
+        Some(challenge(&secret, &form.name, protection.pow_difficulty))
+    }else{
+        None
+    };
+    Trap{
+        honeypot: honeypot(protection, fields).map(|name| name.to_string()),
+        token,
+        challenge,
+        error: None,
+    }
+}
+
+/// Checks the honeypot, the time trap and the proof of work of a
+/// submission. A solved challenge is spent, so it can not be used again.
+pub async fn check(pool: &SqlitePool, form: &Form, fields: &[Field], protection: &Protection,
+        data: &HashMap<String, String>) -> Verdict{
+    let filled = honeypot(protection, fields)
+        .and_then(|name| data.get(name))
+        .map(|value| !value.is_empty())
+        .unwrap_or(false);
+    if filled{
+        return Verdict::Bot;
+    }
+    if protection.min_fill_seconds > 0{
+        let secret = Param::get_form_secret(pool).await;
+        let age = data.get(TOKEN_FIELD)
+            .and_then(|token| token_age(&secret, &form.name, token));
+        match age{
+            Some(age) if age >= protection.min_fill_seconds => {},
+            _ => return Verdict::TooFast,
+        }
+    }
+    if protection.pow_difficulty > 0{
+        let secret = Param::get_form_secret(pool).await;
+        let nonce = match (data.get(CHALLENGE_FIELD), data.get(SOLUTION_FIELD)){
+            (Some(challenge), Some(solution)) => proven(&secret, &form.name,
+                protection.pow_difficulty, challenge, solution),
+            _ => None,
+        };
+        let expires_at = Utc::now() + chrono::Duration::seconds(MAX_TOKEN_AGE);
+        let spent = match nonce{
+            Some(nonce) => Challenge::spend(pool, &nonce, expires_at)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Can not spend challenge of {}: {}", form.name, e);
+                    false
+                }),
+            None => false,
+        };
+        if !spent{
+            return Verdict::Unproven;
+        }
+    }
+    Verdict::Human
+}
+
+struct Bucket{
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets of the submissions to every form from every address,
+/// shared by all the workers.
+#[derive(Default)]
+pub struct RateLimiter{
+    buckets: Mutex<HashMap<(String, String), Bucket>>,
+}
+
+impl RateLimiter{
+    /// Takes a token from the bucket of `key`, which holds up to `limit` and
+    /// gets them back over `window` seconds. Without tokens, returns the
+    /// seconds until the next one.
+    fn take(&self, key: (String, String), limit: i64, window: i64) -> Result<(), u64>{
+        let capacity = limit as f64;
+        let rate = capacity / window.max(1) as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() > MAX_BUCKETS{
+            buckets.retain(|_, bucket| bucket.tokens +
+                now.duration_since(bucket.updated_at).as_secs_f64() * rate < capacity);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket{tokens: capacity, updated_at: now});
+        bucket.tokens = (bucket.tokens +
+            now.duration_since(bucket.updated_at).as_secs_f64() * rate).min(capacity);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0{
+            bucket.tokens -= 1.0;
+            Ok(())
+        }else{
+            Err(((1.0 - bucket.tokens) / rate).ceil() as u64)
+        }
+    }
+}
+
+fn too_many_requests(template: Option<&web::Data<Tera>>, retry_after: u64) -> Error{
+    let html = template
+        .and_then(|template| template.render("429.html", &tera::Context::new())
+            .map_err(|e| error!("Template error: {}", e))
+            .ok())
+        .unwrap_or_else(|| "Too many requests".to_string());
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .content_type("text/html; charset=utf-8")
+        .body(html);
+    error::InternalError::from_response("Too many requests", response).into()
+}
+
+/// Middleware for the public forms. Limits the submissions to every form
+/// from the same address to the `rate_limit` of its protection.
+pub async fn rate_limit(req: ServiceRequest, next: Next<impl MessageBody>)
+        -> Result<ServiceResponse<impl MessageBody>, Error>{
+    if req.method() != Method::POST{
+        return next.call(req).await;
+    }
+    let table = req.path().trim_matches('/').to_string();
+    let (pool, conf, limiter) = match (req.app_data::<web::Data<SqlitePool>>(),
+            req.app_data::<web::Data<Configuration>>(),
+            req.app_data::<web::Data<RateLimiter>>()){
+        (Some(pool), Some(conf), Some(limiter)) => (pool.clone(), conf.clone(), limiter.clone()),
+        _ => return next.call(req).await,
+    };
+    let protection = match Form::read_by_name(pool.get_ref(), &table).await{
+        Ok(form) => Protection::read(pool.get_ref(), form.id).await.ok(),
+        Err(_) => None,
+    };
+    if let Some(protection) = protection.filter(|protection| protection.rate_limit > 0){
+        let ip = origin(req.request(), &conf).ip.unwrap_or_default();
+        if let Err(retry_after) = limiter.take((table.clone(), ip.clone()),
+                protection.rate_limit, protection.rate_window){
+            debug!("Rate limit of {} reached by {}", table, ip);
+            return Err(too_many_requests(req.app_data::<web::Data<Tera>>(), retry_after));
+        }
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    fn token(secret: &str, form: &str, issued_at: i64) -> String{
+        format!("{}.{}", issued_at, signature(secret, &format!("{}.{}", form, issued_at)))
+    }
+
+    fn zero_bits(challenge: &str, solution: &str) -> u32{
+        leading_zero_bits(&Sha256::digest(format!("{}:{}", challenge, solution).as_bytes()))
+    }
+
+    /// First solution with at least, or less than, `bits` leading zero bits.
+    fn solve(challenge: &str, bits: u32, enough: bool) -> String{
+        (0..).map(|n: u64| n.to_string())
+            .find(|solution| (zero_bits(challenge, solution) >= bits) == enough)
+            .unwrap()
+    }
+
+    #[test]
+    fn token_age_of_a_valid_token(){
+        let now = Utc::now().timestamp();
+        let age = token_age(SECRET, "contact", &token(SECRET, "contact", now - 60));
+        assert!(matches!(age, Some(60..=61)));
+    }
+
+    #[test]
+    fn token_age_rejects_invalid_tokens(){
+        let now = Utc::now().timestamp();
+        assert_eq!(token_age(SECRET, "other", &token(SECRET, "contact", now)), None);
+        assert_eq!(token_age(SECRET, "contact", &token("other", "contact", now)), None);
+        assert_eq!(token_age(SECRET, "contact",
+            &token(SECRET, "contact", now - MAX_TOKEN_AGE - 10)), None);
+        assert_eq!(token_age(SECRET, "contact", &token(SECRET, "contact", now + 60)), None);
+        assert_eq!(token_age(SECRET, "contact", "token"), None);
+        assert_eq!(token_age(SECRET, "contact", ""), None);
+    }
+
+    #[test]
+    fn leading_zero_bits_stops_at_the_first_one(){
+        assert_eq!(leading_zero_bits(&[]), 0);
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x0f, 0x00]), 4);
+        assert_eq!(leading_zero_bits(&[0x00, 0x80]), 8);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x01]), 23);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn proven_takes_a_solution(){
+        let challenge = challenge(SECRET, "contact", 4);
+        let nonce = challenge.split('.').nth(1).unwrap().to_string();
+        let solution = solve(&challenge, 4, true);
+        assert_eq!(proven(SECRET, "contact", 4, &challenge, &solution), Some(nonce.clone()));
+        assert_eq!(proven(SECRET, "contact", 2, &challenge, &solution), Some(nonce));
+    }
+
+    #[test]
+    fn proven_rejects_invalid_solutions(){
+        let challenge = challenge(SECRET, "contact", 4);
+        let solution = solve(&challenge, 4, true);
+        let wrong = solve(&challenge, 4, false);
+        assert_eq!(proven(SECRET, "contact", 4, &challenge, &wrong), None);
+        assert_eq!(proven(SECRET, "contact", 5, &challenge, &solution), None);
+        assert_eq!(proven(SECRET, "other", 4, &challenge, &solution), None);
+        assert_eq!(proven("other", "contact", 4, &challenge, &solution), None);
+        assert_eq!(proven(SECRET, "contact", 4, "challenge", &solution), None);
+    }
+
+    #[test]
+    fn proven_rejects_a_lowered_difficulty(){
+        let challenge = challenge(SECRET, "contact", 4);
+        let (content, signed) = challenge.rsplit_once('.').unwrap();
+        let (start, _) = content.rsplit_once('.').unwrap();
+        let tampered = format!("{}.0.{}", start, signed);
+        let solution = solve(&tampered, 0, true);
+        assert_eq!(proven(SECRET, "contact", 0, &tampered, &solution), None);
+    }
+
+    #[test]
+    fn proven_rejects_expired_challenges(){
+        let issued_at = Utc::now().timestamp() - MAX_TOKEN_AGE - 10;
+        let content = format!("{}.nonce.1", issued_at);
+        let challenge = format!("{}.{}", content,
+            signature(SECRET, &format!("pow.contact.{}", content)));
+        let solution = solve(&challenge, 1, true);
+        assert_eq!(proven(SECRET, "contact", 1, &challenge, &solution), None);
+    }
+}
@@ -0,0 +1,144 @@
+use actix_web::{get, post, web, Result, error, Error, Responder, HttpResponse};
+use actix_web_lab::respond::Html;
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use log::{debug, error};
+
+use crate::config::Configuration;
+use crate::models::{Form, Field, User, Param};
+use crate::uploads;
+use crate::admin::{Message, redirect, redirect_with_error, render};
+use crate::http::{auth::Admin, csrf};
+
+/// Submissions of a form in the trash, as text.
+#[derive(Debug, Serialize)]
+struct DeletedRows{
+    form: Form,
+    fields: Vec<Field>,
+    rows: Vec<HashMap<String, String>>,
+}
+
+fn to_text(value: &Value) -> String{
+    match value{
+        Value::Null => "".to_string(),
+        Value::String(value) => value.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn database_error(e: crate::models::Error) -> Error{
+    error!("Database error: {}", e);
+    error::ErrorInternalServerError("Database error")
+}
+
+/// Everything that has been deleted and can still be restored.
+#[get("/trash")]
+pub async fn get_trash(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        message: web::Query<Message>) -> Result<impl Responder, Error>{
+    let forms = Form::read_all(pool.get_ref())
+        .await
+        .map_err(database_error)?;
+    let names: HashMap<i64, String> = forms.iter()
+        .map(|form| (form.id, form.name.clone()))
+        .collect();
+    let mut submissions = Vec::new();
+    for form in forms{
+        let fields = form.get_fields(pool.get_ref())
+            .await
+            .map_err(database_error)?;
+        let rows: Vec<HashMap<String, String>> = form.read_deleted_rows(pool.get_ref())
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| Form::row_to_json(&fields, row)
+                .iter()
+                .map(|(key, value)| (key.to_string(), to_text(value)))
+                .collect())
+            .collect();
+        if !rows.is_empty(){
+            submissions.push(DeletedRows{form, fields, rows});
+        }
+    }
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("forms", &Form::read_deleted(pool.get_ref())
+        .await
+        .map_err(database_error)?);
+    ctx.insert("fields", &Field::read_deleted(pool.get_ref())
+        .await
+        .map_err(database_error)?);
+    ctx.insert("names", &names);
+    ctx.insert("users", &User::read_deleted(pool.get_ref())
+        .await
+        .map_err(database_error)?);
+    ctx.insert("submissions", &submissions);
+    ctx.insert("retention", &Param::get_trash_retention_days(pool.get_ref()).await);
+    ctx.insert("error", &message.error);
+    Ok(Html(render(&template, "admin/trash.html", &ctx)?))
+}
+
+/// Restores or purges a form, a field or a user in the trash. The files of
+/// the uploads purged with a form or a field are removed in the background.
+#[post("/trash/{kind}/{id}/{action}")]
+pub async fn post_trash(_admin: Admin, pool: web::Data<SqlitePool>,
+        conf: web::Data<Configuration>, path: web::Path<(String, i64, String)>)
+        -> Result<HttpResponse, Error>{
+    let (kind, id, action) = path.into_inner();
+    let pool = pool.get_ref();
+    let result = match (kind.as_str(), action.as_str()){
+        ("forms", "restore") => Form::restore(pool, id).await.map(|_| ()),
+        ("forms", "purge") => Form::purge(pool, id).await.map(|_| ()),
+        ("fields", "restore") => Field::restore(pool, id).await.map(|_| ()),
+        ("fields", "purge") => Field::purge(pool, id).await.map(|_| ()),
+        ("users", "restore") => User::restore(pool, id).await.map(|_| ()),
+        ("users", "purge") => User::purge(pool, id).await.map(|_| ()),
+        _ => return Err(error::ErrorNotFound("Not found")),
+    };
+    match result{
+        Ok(_) => {
+            debug!("{} {} {}", action, kind, id);
+            if action == "purge"{
+                actix_web::rt::spawn(uploads::sweep(pool.clone(), conf.get_ref().clone()));
+            }
+            Ok(redirect("/admin/trash"))
+        },
+        Err(e) => {
+            error!("Can not {} {} {}: {}", action, kind, id, e);
+            Ok(redirect_with_error("/admin/trash", &format!("Can not {} it", action)))
+        },
+    }
+}
+
+/// Restores or purges a submission in the trash, removing the files of its
+/// uploads in the background.
+#[post("/trash/submissions/{form_id}/{id}/{action}")]
+pub async fn post_trash_submission(_admin: Admin, pool: web::Data<SqlitePool>,
+        conf: web::Data<Configuration>, path: web::Path<(i64, i64, String)>)
+        -> Result<HttpResponse, Error>{
+    let (form_id, id, action) = path.into_inner();
+    let form = Form::read(pool.get_ref(), form_id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Form not found"))?;
+    let result = match action.as_str(){
+        "restore" => form.restore_row(pool.get_ref(), id).await,
+        "purge" => form.purge_row(pool.get_ref(), id).await,
+        _ => return Err(error::ErrorNotFound("Not found")),
+    };
+    match result{
+        Ok(_) => {
+            debug!("{} submission {} of {}", action, id, form.name);
+            if action == "purge"{
+                actix_web::rt::spawn(uploads::sweep(pool.get_ref().clone(),
+                    conf.get_ref().clone()));
+            }
+            Ok(redirect("/admin/trash"))
+        },
+        Err(e) => {
+            error!("Can not {} submission {} of {}: {}", action, id, form.name, e);
+            Ok(redirect_with_error("/admin/trash", &format!("Can not {} it", action)))
+        },
+    }
+}
@@ -0,0 +1,593 @@
+use actix_web::{get, web, Error, HttpRequest, HttpResponse, error,
+    http::header::{self, ContentDisposition}};
+use actix_multipart::Multipart;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqlitePool;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use log::{debug, error};
+
+use crate::config::Configuration;
+use crate::http::auth::Admin;
+use crate::models::{self, Form, Field, Upload, validate_field};
+
+/// Largest file of a field without a `max_size`, in bytes.
+const MAX_FILE_SIZE: i64 = 10 * 1024 * 1024;
+
+/// Largest value of a text input of a multipart form, in bytes.
+const MAX_TEXT_SIZE: usize = 64 * 1024;
+
+/// Largest form without files, in bytes.
+const MAX_FORM_SIZE: usize = 256 * 1024;
+
+/// Most values and files a multipart form can have.
+const MAX_PARTS: usize = 256;
+
+/// Largest multipart form, with all its files, in bytes.
+pub(crate) const MAX_MULTIPART_SIZE: i64 = 64 * 1024 * 1024;
+
+/// Seconds a file of the upload directory is kept even if no upload is in
+/// it, as it may have been written for a submission that is being saved.
+const UNUSED_GRACE: u64 = 3600;
+
+/// A file as it was sent, before it is stored.
+pub struct Attachment{
+    pub name: String,
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+/// The values and the files of a submission, with the error of every file
+/// that was too large or of a type the field does not take.
+#[derive(Default)]
+pub struct Submission{
+    pub data: HashMap<String, String>,
+    pub files: HashMap<String, Attachment>,
+    pub errors: HashMap<String, String>,
+}
+
+fn is_file(field: &Field) -> bool{
+    field.get_datatype() == "file"
+}
+
+fn max_size(field: &Field) -> i64{
+    field.get_constraints().max_size.unwrap_or(MAX_FILE_SIZE)
+}
+
+/// Checks the type of a file against those of the field, where `image/*`
+/// takes any image.
+fn mime_allowed(field: &Field, mime: &str) -> bool{
+    let types = &field.get_constraints().mime_types;
+    types.is_empty() || types.iter().any(|allowed| match allowed.strip_suffix("/*"){
+        Some(kind) => mime.split('/').next() == Some(kind),
+        None => allowed == mime,
+    })
+}
+
+fn human_size(bytes: i64) -> String{
+    if bytes >= 1024 * 1024{
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }else{
+        format!("{} KB", (bytes + 1023) / 1024)
+    }
+}
+
+/// Name of the file without the folders some browsers send with it.
+fn base_name(filename: &str) -> String{
+    filename.rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Puts a value of a form in `data`. Every checked box of a `checkboxes`
+/// field sends its own value, and they are gathered in a JSON array.
+fn insert_value(fields: &[Field], data: &mut HashMap<String, String>, name: String,
+        value: String){
+    let multiple = fields.iter()
+        .any(|field| field.get_datatype() == "checkboxes" && field.get_name() == name);
+    if multiple{
+        let mut chosen = data.get(&name)
+            .map(|value| Field::chosen(value))
+            .unwrap_or_default();
+        chosen.push(value);
+        data.insert(name, serde_json::to_string(&chosen).unwrap_or_default());
+    }else{
+        data.insert(name, value);
+    }
+}
+
+/// Adds a chunk of a multipart form to the bytes read so far, refusing the
+/// form once they are too many.
+fn add_to_total(total: &mut i64, chunk: &web::Bytes) -> Result<(), Error>{
+    *total += chunk.len() as i64;
+    if *total > MAX_MULTIPART_SIZE{
+        return Err(error::ErrorPayloadTooLarge("Form too large"));
+    }
+    Ok(())
+}
+
+/// Reads a submission sent as `multipart/form-data`, with its files, or as
+/// an urlencoded form. Values sent as text for a `file` field are dropped, so
+/// a submission can not point to a file it did not upload.
+pub async fn read(req: &HttpRequest, mut payload: web::Payload, fields: &[Field])
+        -> Result<Submission, Error>{
+    let multipart = req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("multipart/form-data"))
+        .unwrap_or(false);
+    let mut submission = Submission::default();
+    if !multipart{
+        let mut body = web::BytesMut::new();
+        while let Some(chunk) = payload.next().await{
+            let chunk = chunk?;
+            if body.len() + chunk.len() > MAX_FORM_SIZE{
+                return Err(error::ErrorPayloadTooLarge("Form too large"));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let pairs: Vec<(String, String)> = serde_urlencoded::from_bytes(&body)
+            .map_err(error::ErrorBadRequest)?;
+        for (name, value) in pairs{
+            if !fields.iter().any(|field| is_file(field) && field.get_name() == name){
+                insert_value(fields, &mut submission.data, name, value);
+            }
+        }
+        return Ok(submission);
+    }
+    let mut multipart = Multipart::new(req.headers(), payload);
+    let mut parts = 0;
+    let mut total = 0;
+    while let Some(part) = multipart.next().await{
+        parts += 1;
+        if parts > MAX_PARTS{
+            return Err(error::ErrorPayloadTooLarge("Too many values"));
+        }
+        let mut part = part?;
+        let disposition = part.content_disposition();
+        let name = disposition.get_name().unwrap_or_default().to_string();
+        let filename = disposition.get_filename().map(base_name);
+        let field = fields.iter()
+            .find(|field| is_file(field) && field.get_name() == name);
+        match (field, filename){
+            (Some(field), Some(filename)) => {
+                let limit = max_size(field);
+                let mime = part.content_type()
+                    .map(|mime| mime.essence_str().to_lowercase())
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                let mut bytes = Vec::new();
+                let mut size: i64 = 0;
+                // Files over the limit are read to the end but not kept
+                while let Some(chunk) = part.next().await{
+                    let chunk = chunk?;
+                    add_to_total(&mut total, &chunk)?;
+                    size += chunk.len() as i64;
+                    if size <= limit{
+                        bytes.extend_from_slice(&chunk);
+                    }
+                }
+                if filename.is_empty() && size == 0{
+                    // No file was chosen
+                    continue;
+                }
+                if size > limit{
+                    debug!("File of {} too large: {} bytes", name, size);
+                    submission.errors.insert(name,
+                        format!("The file can not be larger than {}", human_size(limit)));
+                }else if !mime_allowed(field, &mime){
+                    debug!("File of {} of type {} not allowed", name, mime);
+                    submission.errors.insert(name, format!("The file must be of type {}",
+                        field.get_constraints().mime_types.join(", ")));
+                }else{
+                    submission.files.insert(name, Attachment{name: filename, mime, bytes});
+                }
+            },
+            (Some(_), None) => {
+                while let Some(chunk) = part.next().await{
+                    add_to_total(&mut total, &chunk?)?;
+                }
+            },
+            (None, _) => {
+                let mut bytes = Vec::new();
+                while let Some(chunk) = part.next().await{
+                    let chunk = chunk?;
+                    add_to_total(&mut total, &chunk)?;
+                    if bytes.len() + chunk.len() > MAX_TEXT_SIZE{
+                        return Err(error::ErrorPayloadTooLarge("Value too large"));
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+                let value = String::from_utf8(bytes)
+                    .map_err(error::ErrorBadRequest)?;
+                insert_value(fields, &mut submission.data, name, value);
+            },
+        }
+    }
+    Ok(submission)
+}
+
+/// Checks a submission against the fields of its form like `validate`. A
+/// `file` field with a new file was already checked by `read`, and one without
+/// it is checked with the upload it has in `data`, if any.
+pub fn validate(fields: &[Field], submission: &Submission) -> HashMap<String, String>{
+    let mut errors = submission.errors.clone();
+    for field in fields{
+        let name = field.get_name();
+        if errors.contains_key(name) || submission.files.contains_key(name){
+            continue;
+        }
+        let value = submission.data.get(name)
+            .map(|value| value.as_str())
+            .unwrap_or("");
+        if let Err(message) = validate_field(field, value){
+            debug!("{}: {}", name, message);
+            errors.insert(name.to_string(), message);
+        }
+    }
+    errors
+}
+
+/// Where a file is kept in the upload directory, after its hash.
+fn path(dir: &str, sha256: &str) -> PathBuf{
+    PathBuf::from(dir)
+        .join(&sha256[..2])
+        .join(sha256)
+}
+
+fn storage_error(e: impl std::fmt::Display) -> Error{
+    error!("Can not store upload: {}", e);
+    error::ErrorInternalServerError("Storage error")
+}
+
+/// Keeps a file in the upload directory, if there is one, or in the
+/// database. Files in the directory are named after their content, so the
+/// same file is only written once.
+async fn save(pool: &SqlitePool, conf: &Configuration, form: &Form, attachment: &Attachment)
+        -> Result<Upload, Error>{
+    let sha256: String = Sha256::digest(&attachment.bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let data = match &conf.upload_dir{
+        Some(dir) => {
+            let path = path(dir, &sha256);
+            if !tokio::fs::try_exists(&path).await.map_err(storage_error)?{
+                if let Some(parent) = path.parent(){
+                    tokio::fs::create_dir_all(parent).await.map_err(storage_error)?;
+                }
+                // Written apart and renamed, so a file is never seen half done
+                let partial = path.with_extension(format!("{}.part", uuid::Uuid::new_v4().simple()));
+                tokio::fs::write(&partial, &attachment.bytes).await.map_err(storage_error)?;
+                tokio::fs::rename(&partial, &path).await.map_err(storage_error)?;
+            }
+            None
+        },
+        None => Some(attachment.bytes.as_slice()),
+    };
+    match Upload::create(pool, form.id, &sha256, &attachment.name, &attachment.mime,
+            attachment.bytes.len() as i64, data).await{
+        Ok(upload) => Ok(upload),
+        Err(e) => {
+            if let Some(dir) = &conf.upload_dir{
+                remove_if_unused(pool, dir, &sha256).await;
+            }
+            Err(storage_error(e))
+        },
+    }
+}
+
+/// Stores the files of a submission and puts the id of every upload in its
+/// data, in place of the file. Returns the uploads, to `discard` them if the
+/// submission can not be saved. If a file can not be stored, those stored
+/// before it are discarded.
+pub async fn store(pool: &SqlitePool, conf: &Configuration, form: &Form,
+        submission: &mut Submission) -> Result<Vec<Upload>, Error>{
+    let mut stored = Vec::new();
+    for (name, attachment) in submission.files.drain(){
+        match save(pool, conf, form, &attachment).await{
+            Ok(upload) => {
+                debug!("File {} of {} stored as upload {}", attachment.name, name, upload.id);
+                submission.data.insert(name, upload.id.to_string());
+                stored.push(upload);
+            },
+            Err(e) => {
+                discard(pool, conf, stored).await;
+                return Err(e);
+            },
+        }
+    }
+    Ok(stored)
+}
+
+/// Removes the file named after `sha256` unless an upload is still in it.
+async fn remove_if_unused(pool: &SqlitePool, dir: &str, sha256: &str){
+    match Upload::is_on_disk(pool, sha256).await{
+        Ok(false) => match tokio::fs::remove_file(path(dir, sha256)).await{
+            Ok(_) => debug!("Removed unused file {}", sha256),
+            Err(e) => error!("Can not remove unused file {}: {}", sha256, e),
+        },
+        Ok(true) => {},
+        Err(e) => error!("Can not check if file {} is used: {}", sha256, e),
+    }
+}
+
+/// Deletes the uploads stored for a submission that could not be saved,
+/// and their files if no other upload is in them.
+pub async fn discard(pool: &SqlitePool, conf: &Configuration, uploads: Vec<Upload>){
+    for upload in uploads{
+        if let Err(e) = Upload::delete(pool, upload.id).await{
+            error!("Can not delete upload {}: {}", upload.id, e);
+            continue;
+        }
+        debug!("Discarded upload {}", upload.id);
+        if let (None, Some(dir)) = (&upload.data, &conf.upload_dir){
+            remove_if_unused(pool, dir, &upload.sha256).await;
+        }
+    }
+}
+
+/// The uploads a submission had in the file fields sent again, to be
+/// discarded once the new ones are saved in their place.
+pub async fn replaced(pool: &SqlitePool, fields: &[Field], values: &HashMap<String, String>,
+        submission: &Submission) -> Vec<Upload>{
+    let mut replaced = Vec::new();
+    for field in fields.iter().filter(|field| is_file(field)){
+        if !submission.files.contains_key(field.get_name()){
+            continue;
+        }
+        let id = match values.get(field.get_name()).map(|value| value.parse::<i64>()){
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        match Upload::read(pool, id).await{
+            Ok(upload) => replaced.push(upload),
+            Err(e) => error!("Can not read upload {}: {}", id, e),
+        }
+    }
+    replaced
+}
+
+/// Removes the files of the upload directory no upload is in any more, as
+/// their forms, fields or submissions were purged, and those left half
+/// written. Returns how many files were removed.
+async fn remove_unused(pool: &SqlitePool, dir: &str) -> Result<u64, models::Error>{
+    let mut removed = 0;
+    let mut folders = match tokio::fs::read_dir(dir).await{
+        Ok(folders) => folders,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(folder) = folders.next_entry().await?{
+        if !folder.file_type().await?.is_dir(){
+            continue;
+        }
+        let mut files = tokio::fs::read_dir(folder.path()).await?;
+        while let Some(file) = files.next_entry().await?{
+            let metadata = file.metadata().await?;
+            let age = metadata.modified()?.elapsed().unwrap_or_default();
+            if !metadata.is_file() || age.as_secs() < UNUSED_GRACE{
+                continue;
+            }
+            let name = file.file_name().to_string_lossy().to_string();
+            // Half written files end with `.part`
+            if name.contains('.') || !Upload::is_on_disk(pool, &name).await?{
+                tokio::fs::remove_file(file.path()).await?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the unused files of the upload directory, if there is one. Call
+/// it after purging forms, fields or submissions.
+pub async fn sweep(pool: SqlitePool, conf: Configuration){
+    if let Some(dir) = &conf.upload_dir{
+        match remove_unused(&pool, dir).await{
+            Ok(removed) => debug!("Removed {} unused files from {}", removed, dir),
+            Err(e) => error!("Can not remove unused files from {}: {}", dir, e),
+        }
+    }
+}
+
+async fn content(conf: &Configuration, upload: Upload) -> Result<Vec<u8>, Error>{
+    match (upload.data, &conf.upload_dir){
+        (Some(data), _) => Ok(data),
+        (None, Some(dir)) => tokio::fs::read(path(dir, &upload.sha256))
+            .await
+            .map_err(|e| {
+                error!("Can not read upload {}: {}", upload.id, e);
+                error::ErrorNotFound("File not found")
+            }),
+        (None, None) => {
+            error!("Upload {} is on disk but there is no upload_dir", upload.id);
+            Err(error::ErrorNotFound("File not found"))
+        },
+    }
+}
+
+/// A file sent with a submission of the form, always as an attachment so
+/// the browser does not render it.
+#[get("/{table}/files/{id:\\d+}")]
+pub async fn get_file(_admin: Admin, pool: web::Data<SqlitePool>,
+        conf: web::Data<Configuration>, path: web::Path<(String, i64)>)
+        -> Result<HttpResponse, Error>{
+    let (table, id) = path.into_inner();
+    let form = Form::read_by_name(pool.get_ref(), &table)
+        .await
+        .map_err(|_| error::ErrorNotFound("Form not found"))?;
+    let upload = Upload::read(pool.get_ref(), id)
+        .await
+        .ok()
+        .filter(|upload| upload.form_id == form.id)
+        .ok_or_else(|| error::ErrorNotFound("File not found"))?;
+    debug!("Downloading upload {} of {}", id, form.name);
+    let mime = upload.mime.clone();
+    let name = upload.name.clone();
+    let bytes = content(&conf, upload).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(mime)
+        .insert_header(ContentDisposition::attachment(name))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .body(bytes))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::models::Constraints;
+
+    fn field(mime_types: &[&str]) -> Field{
+        Field::sample("file", "file", false, Constraints{
+            mime_types: mime_types.iter().map(|mime| mime.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn mime_allowed_without_types_takes_any(){
+        assert!(mime_allowed(&field(&[]), "application/x-msdownload"));
+    }
+
+    #[test]
+    fn mime_allowed_checks_types_and_wildcards(){
+        let field = field(&["application/pdf", "image/*"]);
+        assert!(mime_allowed(&field, "application/pdf"));
+        assert!(mime_allowed(&field, "image/png"));
+        assert!(mime_allowed(&field, "image/svg+xml"));
+        assert!(!mime_allowed(&field, "text/plain"));
+        assert!(!mime_allowed(&field, "application/pdfx"));
+        assert!(!mime_allowed(&field, "imagex/png"));
+    }
+
+    #[test]
+    fn base_name_drops_folders(){
+        assert_eq!(base_name("cv.pdf"), "cv.pdf");
+        assert_eq!(base_name("docs/cv.pdf"), "cv.pdf");
+        assert_eq!(base_name("C:\\Users\\me\\cv.pdf"), "cv.pdf");
+        assert_eq!(base_name("../../etc/passwd"), "passwd");
+        assert_eq!(base_name("docs/"), "");
+        assert_eq!(base_name(""), "");
+    }
+
+    /// A configuration that keeps the uploads in a new temporary folder.
+    fn conf() -> Configuration{
+        let dir = std::env::temp_dir()
+            .join(format!("asker-uploads-{}", uuid::Uuid::new_v4().simple()));
+        Configuration::new(&format!("log_level: info\ndb_url: \"sqlite::memory:\"\nport: 8080\n\
+            username: admin\npassword: admin\nupload_dir: {}\n", dir.display())).unwrap()
+    }
+
+    /// A form with a `cv` file field and a unique `email`.
+    async fn form(pool: &SqlitePool) -> (Form, Vec<Field>){
+        crate::migrations::test_form(pool, "jobs", vec![
+            crate::migrations::test_field("cv", "file"),
+            models::NewField{unique: true, ..crate::migrations::test_field("email", "email")},
+        ]).await
+    }
+
+    fn submission(content: &str) -> Submission{
+        let mut submission = Submission::default();
+        submission.data.insert("email".to_string(), "me@example.com".to_string());
+        submission.files.insert("cv".to_string(), Attachment{
+            name: "cv.pdf".to_string(),
+            mime: "application/pdf".to_string(),
+            bytes: content.as_bytes().to_vec(),
+        });
+        submission
+    }
+
+    #[actix_web::test]
+    async fn discard_removes_the_uploads_of_a_failed_insert(){
+        let pool = crate::migrations::test_pool().await;
+        let conf = conf();
+        let (form, fields) = form(&pool).await;
+        let mut first = submission("first");
+        let stored = store(&pool, &conf, &form, &mut first).await.unwrap();
+        form.insert(&pool, &fields, &first.data, &Default::default()).await.unwrap();
+        let kept = stored[0].clone();
+        // The same email again, with the same file and a new one
+        let mut second = submission("first");
+        second.files.insert("other".to_string(), Attachment{
+            name: "other.pdf".to_string(),
+            mime: "application/pdf".to_string(),
+            bytes: b"second".to_vec(),
+        });
+        let stored = store(&pool, &conf, &form, &mut second).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        let error = form.insert(&pool, &fields, &second.data, &Default::default()).await.unwrap_err();
+        assert!(form.duplicated(&fields, &error).is_some());
+        discard(&pool, &conf, stored.clone()).await;
+        let dir = conf.upload_dir.as_deref().unwrap();
+        for upload in stored{
+            assert!(Upload::read(&pool, upload.id).await.is_err());
+        }
+        // The file of the first submission is still in use
+        assert!(Upload::read(&pool, kept.id).await.is_ok());
+        assert!(path(dir, &kept.sha256).exists());
+        let second_sha: String = Sha256::digest(b"second").iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert!(!path(dir, &second_sha).exists());
+    }
+
+    #[actix_web::test]
+    async fn purge_row_deletes_its_uploads(){
+        let pool = crate::migrations::test_pool().await;
+        let conf = conf();
+        let (form, fields) = form(&pool).await;
+        let mut submission = submission("cv");
+        let stored = store(&pool, &conf, &form, &mut submission).await.unwrap();
+        let id = form.insert(&pool, &fields, &submission.data, &Default::default()).await.unwrap();
+        form.delete_row(&pool, id).await.unwrap();
+        form.purge_row(&pool, id).await.unwrap();
+        assert!(Upload::read(&pool, stored[0].id).await.is_err());
+        assert!(!Upload::is_on_disk(&pool, &stored[0].sha256).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn replaced_uploads_are_discarded_after_an_update(){
+        let pool = crate::migrations::test_pool().await;
+        let conf = conf();
+        let (form, fields) = form(&pool).await;
+        let mut first = submission("first");
+        let old = store(&pool, &conf, &form, &mut first).await.unwrap();
+        let id = form.insert(&pool, &fields, &first.data, &Default::default()).await.unwrap();
+        let mut second = submission("second");
+        let previous = replaced(&pool, &fields, &first.data, &second).await;
+        assert_eq!(previous.len(), 1);
+        assert_eq!(previous[0].id, old[0].id);
+        let new = store(&pool, &conf, &form, &mut second).await.unwrap();
+        form.update_row(&pool, &fields, id, &second.data).await.unwrap();
+        discard(&pool, &conf, previous).await;
+        assert!(Upload::read(&pool, old[0].id).await.is_err());
+        assert!(!path(conf.upload_dir.as_deref().unwrap(), &old[0].sha256).exists());
+        assert!(Upload::read(&pool, new[0].id).await.is_ok());
+        // Without a new file the upload is kept
+        assert!(replaced(&pool, &fields, &second.data, &Submission::default()).await.is_empty());
+    }
+
+    async fn read_body(parts: usize) -> Result<Submission, Error>{
+        use actix_web::FromRequest;
+        let mut body = String::new();
+        for index in 0..parts{
+            body.push_str(&format!("--boundary\r\nContent-Disposition: form-data; \
+                name=\"value{}\"\r\n\r\nx\r\n", index));
+        }
+        body.push_str("--boundary--\r\n");
+        let (req, mut payload) = actix_web::test::TestRequest::post()
+            .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=boundary"))
+            .set_payload(body)
+            .to_http_parts();
+        let payload = web::Payload::from_request(&req, &mut payload).await.unwrap();
+        read(&req, payload, &[]).await
+    }
+
+    #[actix_web::test]
+    async fn read_limits_the_parts(){
+        assert_eq!(read_body(MAX_PARTS).await.unwrap().data.len(), MAX_PARTS);
+        assert!(read_body(MAX_PARTS + 1).await.is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use actix_web::{get, post, web, Result, error, Error, Responder, HttpResponse};
+use actix_web_lab::respond::Html;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+use tera::Tera;
+use std::time::Duration;
+use log::{debug, error};
+
+use crate::models::{self, Form, Field, Delivery, Param, Webhook};
+use crate::admin::{Message, read_form, redirect, redirect_with_error, render};
+use crate::http::{auth::Admin, csrf};
+
+/// Seconds between two looks for pending deliveries.
+const POLL_INTERVAL: u64 = 5;
+
+/// Deliveries made on every look.
+const BATCH: i64 = 20;
+
+/// Seconds a webhook has to answer.
+const TIMEOUT: u64 = 10;
+
+/// Deliveries shown in the page of a webhook.
+const LOG_SIZE: i64 = 50;
+
+/// Header with the HMAC-SHA256 of the body, as `sha256=` and its hex.
+pub const SIGNATURE_HEADER: &str = "X-Asker-Signature";
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookSchema{
+    url: String,
+    secret: String,
+    active: Option<String>,
+}
+
+/// Signature of `body` with the secret of a webhook.
+pub fn sign(secret: &str, body: &str) -> String{
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    let signature: String = mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", signature)
+}
+
+fn new_secret() -> String{
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+fn is_valid_url(url: &str) -> bool{
+    reqwest::Url::parse(url)
+        .map(|url| url.scheme() == "http" || url.scheme() == "https")
+        .unwrap_or(false)
+}
+
+/// Queues a new submission for the webhooks of its form. Call it once the
+/// submission is saved; a failure is logged and does not undo it.
+pub async fn submitted(pool: &SqlitePool, form: &Form, fields: &[Field], id: i64){
+    let row = match form.read_row(pool, id).await{
+        Ok(row) => row,
+        Err(e) => {
+            error!("Can not read submission {} of {}: {}", id, form.name, e);
+            return;
+        },
+    };
+    let payload = json!({
+        "event": "submission.created",
+        "form": form.name,
+        "submission": Form::row_to_json(fields, &row),
+    });
+    match Webhook::enqueue(pool, form.id, "submission.created", &payload).await{
+        Ok(queued) => debug!("Queued {} deliveries of {} of {}", queued, id, form.name),
+        Err(e) => error!("Can not queue deliveries of {} of {}: {}", id, form.name, e),
+    }
+}
+
+/// Makes a request of a delivery, returning the status of the answer and an
+/// error when it is not a 2xx one.
+async fn send(client: &reqwest::Client, webhook: &Webhook, delivery: &Delivery)
+        -> (Option<i64>, Option<String>){
+    let result = client.post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, &delivery.payload))
+        .header("X-Asker-Event", &delivery.event)
+        .header("X-Asker-Delivery", delivery.id.to_string())
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    match result{
+        Ok(response) if response.status().is_success() =>
+            (Some(response.status().as_u16() as i64), None),
+        Ok(response) => (Some(response.status().as_u16() as i64),
+            Some(format!("The webhook answered {}", response.status()))),
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+/// Makes the deliveries whose time has come.
+async fn deliver_due(pool: &SqlitePool, client: &reqwest::Client) -> Result<usize, models::Error>{
+    let max_attempts = Param::get_webhook_max_attempts(pool).await;
+    let due = Delivery::read_due(pool, BATCH).await?;
+    for delivery in due.iter(){
+        let webhook = Webhook::read(pool, delivery.webhook_id).await?;
+        let (status, error) = send(client, &webhook, delivery).await;
+        if let Some(error) = &error{
+            debug!("Delivery {} to {} failed: {}", delivery.id, webhook.url, error);
+        }
+        delivery.attempted(pool, status, error, max_attempts).await?;
+    }
+    Ok(due.len())
+}
+
+/// Makes the pending deliveries while the server runs. Being in the
+/// database, they survive restarts.
+pub async fn run(pool: SqlitePool){
+    let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT))
+            .build(){
+        Ok(client) => client,
+        Err(e) => {
+            error!("Can not create the client of the webhooks, {}", e);
+            return;
+        },
+    };
+    loop{
+        match deliver_due(&pool, &client).await{
+            Ok(0) => actix_web::rt::time::sleep(Duration::from_secs(POLL_INTERVAL)).await,
+            Ok(delivered) => debug!("Made {} webhook deliveries", delivered),
+            Err(e) => {
+                error!("Can not make webhook deliveries, {}", e);
+                actix_web::rt::time::sleep(Duration::from_secs(POLL_INTERVAL)).await;
+            },
+        }
+    }
+}
+
+fn database_error(e: models::Error) -> Error{
+    error!("Database error: {}", e);
+    error::ErrorInternalServerError("Database error")
+}
+
+async fn read_webhook(pool: &SqlitePool, id: i64) -> Result<Webhook, Error>{
+    Webhook::read(pool, id)
+        .await
+        .map_err(|_| error::ErrorNotFound("Webhook not found"))
+}
+
+#[post("/forms/{id}/webhooks")]
+pub async fn post_webhooks(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<WebhookSchema>) -> Result<HttpResponse, Error>{
+    let form = read_form(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/forms/{}", form.id);
+    let url = data.url.trim();
+    if !is_valid_url(url){
+        return Ok(redirect_with_error(&location, &format!(
+            "The URL {} is not valid", url)));
+    }
+    let secret = match data.secret.trim(){
+        "" => new_secret(),
+        secret => secret.to_string(),
+    };
+    match Webhook::create(pool.get_ref(), form.id, url, &secret).await{
+        Ok(webhook) => Ok(redirect(&format!("/admin/webhooks/{}", webhook.id))),
+        Err(e) => {
+            error!("Can not create webhook of {}: {}", form.name, e);
+            Ok(redirect_with_error(&location, "Can not create the webhook"))
+        },
+    }
+}
+
+/// A webhook with the log of its last deliveries.
+#[get("/webhooks/{id}")]
+pub async fn get_webhook(admin: Admin, template: web::Data<Tera>, pool: web::Data<SqlitePool>,
+        path: web::Path<i64>, message: web::Query<Message>) -> Result<impl Responder, Error>{
+    let webhook = read_webhook(pool.get_ref(), path.into_inner()).await?;
+    let form = read_form(pool.get_ref(), webhook.form_id).await?;
+    let deliveries = Delivery::read_by_webhook_id(pool.get_ref(), webhook.id, LOG_SIZE)
+        .await
+        .map_err(database_error)?;
+    let mut ctx = csrf::context();
+    ctx.insert("user", &admin.0.name);
+    ctx.insert("form", &form);
+    ctx.insert("webhook", &webhook);
+    ctx.insert("deliveries", &deliveries);
+    ctx.insert("signature_header", SIGNATURE_HEADER);
+    ctx.insert("error", &message.error);
+    Ok(Html(render(&template, "admin/webhook.html", &ctx)?))
+}
+
+/// Saves a webhook. An empty secret gets a new random one.
+#[post("/webhooks/{id}")]
+pub async fn post_webhook(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>,
+        data: web::Form<WebhookSchema>) -> Result<HttpResponse, Error>{
+    let mut webhook = read_webhook(pool.get_ref(), path.into_inner()).await?;
+    let location = format!("/admin/webhooks/{}", webhook.id);
+    let url = data.url.trim();
+    if !is_valid_url(url){
+        return Ok(redirect_with_error(&location, &format!(
+            "The URL {} is not valid", url)));
+    }
+    webhook.url = url.to_string();
+    webhook.secret = match data.secret.trim(){
+        "" => new_secret(),
+        secret => secret.to_string(),
+    };
+    webhook.active = data.active.is_some();
+    match Webhook::update(pool.get_ref(), &webhook).await{
+        Ok(_) => Ok(redirect(&location)),
+        Err(e) => {
+            error!("Can not save webhook {}: {}", webhook.id, e);
+            Ok(redirect_with_error(&location, "Can not save the webhook"))
+        },
+    }
+}
+
+#[post("/webhooks/{id}/delete")]
+pub async fn delete_webhook(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let webhook = read_webhook(pool.get_ref(), path.into_inner()).await?;
+    Webhook::remove(pool.get_ref(), webhook.id)
+        .await
+        .map_err(database_error)?;
+    Ok(redirect(&format!("/admin/forms/{}", webhook.form_id)))
+}
+
+/// Sends the payload of a delivery again, whatever happened to it.
+#[post("/deliveries/{id}/redeliver")]
+pub async fn post_redeliver(_admin: Admin, pool: web::Data<SqlitePool>, path: web::Path<i64>)
+        -> Result<HttpResponse, Error>{
+    let delivery = Delivery::read(pool.get_ref(), path.into_inner())
+        .await
+        .map_err(|_| error::ErrorNotFound("Delivery not found"))?;
+    Delivery::redeliver(pool.get_ref(), delivery.id)
+        .await
+        .map_err(database_error)?;
+    Ok(redirect(&format!("/admin/webhooks/{}", delivery.webhook_id)))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn sign_with_hmac_sha256(){
+        // Test case 2 of RFC 4231
+        assert_eq!(sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn sign_depends_on_the_secret(){
+        assert_ne!(sign("one", "{}"), sign("two", "{}"));
+    }
+}